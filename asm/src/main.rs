@@ -1,3 +1,51 @@
+extern crate chip8;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+use chip8::asm;
+
+fn usage() -> ! {
+    println!("Usage: asm <source> <rom>");
+    println!("  <source> can be - to read it from stdin, the syntax is what chip8 --disasm prints");
+    process::exit(1);
+}
+
+fn read_source(path: &str) -> io::Result<String> {
+    let mut source = String::new();
+
+    if path == "-" {
+        let stdin = io::stdin();
+        try!(stdin.lock().read_to_string(&mut source));
+    } else {
+        let mut file = try!(fs::File::open(path));
+        try!(file.read_to_string(&mut source));
+    }
+
+    Ok(source)
+}
+
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        usage();
+    }
+    let (source_path, rom_path) = (&args[0], &args[1]);
+
+    let source = read_source(source_path).unwrap_or_else(|err| {
+        println!("Could not read '{}': {}", source_path, err);
+        process::exit(1);
+    });
+
+    let rom = asm::assemble(&source).unwrap_or_else(|err| {
+        println!("{}: {}", source_path, err);
+        process::exit(1);
+    });
+
+    if let Err(err) = fs::write(rom_path, &rom) {
+        println!("Could not write '{}': {}", rom_path, err);
+        process::exit(1);
+    }
 }
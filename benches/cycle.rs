@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate criterion;
+extern crate chip8;
+
+use criterion::{Criterion, black_box};
+
+use chip8::machine::Chip8;
+use chip8::opcode::Opcode;
+
+// Adds to V0 and jumps back, so every cycle runs its whole instruction budget without waiting on anything
+const BUSY_LOOP: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+fn cycle(c: &mut Criterion) {
+    for &speed in [0, 7, 63, 511].iter() {
+        c.bench_function(&format!("cycle at speed {}", speed), move |b| {
+            let mut chip8 = Chip8::from_bytes(&BUSY_LOOP).unwrap();
+            chip8.set_speed(speed);
+
+            b.iter(|| chip8.cycle(Some([false; 16])).unwrap());
+        });
+    }
+}
+
+fn decode(c: &mut Criterion) {
+    c.bench_function("decode all 65536 words", |b| {
+        b.iter(|| {
+            for word in 0..0x10000u32 {
+                black_box(Opcode::from_u16(word as u16)).ok();
+            }
+        });
+    });
+}
+
+criterion_group!(benches, cycle, decode);
+criterion_main!(benches);
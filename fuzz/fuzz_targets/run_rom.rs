@@ -0,0 +1,35 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate chip8;
+
+use chip8::machine::{Chip8Builder, Platform};
+
+// Long enough for loops and timers to get going, short enough to keep the fuzzer quick
+const FRAMES: usize = 30;
+
+const PLATFORMS: [Platform; 4] = [Platform::CosmacVip, Platform::SuperChip, Platform::XoChip, Platform::Modern];
+
+// Whatever the ROM, running it has to either work or fail with a RuntimeError, never panic.
+// Run with `cargo fuzz run run_rom`, the seed corpus is in corpus/run_rom
+fuzz_target!(|data: &[u8]| {
+    for platform in PLATFORMS.iter() {
+        let mut chip8 = match Chip8Builder::new().platform(*platform).seed(0).rom(data).build() {
+            Ok(chip8) => chip8,
+            Err(_) => return, // Too large to load
+        };
+
+        for frame in 0..FRAMES {
+            // A different key goes down every other frame and is released on the next, so FX0A waits finish
+            let mut keys = [false; 16];
+            if frame % 2 == 0 {
+                keys[(frame / 2) % 16] = true;
+            }
+
+            if chip8.cycle(Some(keys)).is_err() {
+                break;
+            }
+            chip8.tick_timers();
+        }
+    }
+});
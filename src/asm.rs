@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use machine::PROGRAM_START;
+use opcode::{Opcode, SetRegMode};
+
+/* Assembles the same syntax the disassembler prints, one instruction per line:
+ *
+ *   ; comments run to the end of the line
+ *   start:          ; labels name the address of whatever follows them
+ *       LD V0, 0x1F
+ *       JP start
+ *   sprite: DB 0xF0, 0x90, 0xF0
+ *
+ * Numbers can be decimal, 0x hex or 0b binary, and anywhere an address is expected a label can be used instead
+ */
+
+const MNEMONICS: [&'static str; 26] = [
+    "CLS", "RET", "LOW", "HIGH", "SCD", "SCR", "SCL", "EXIT", "JP", "CALL", "SE", "SNE", "LD", "ADD",
+    "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "PLANE", "SKP", "SKNP",
+];
+
+#[derive(Debug)]
+pub enum AsmError {
+    UnknownInstruction { line: usize, name: String },
+    InvalidOperands { line: usize, text: String },
+    InvalidNumber { line: usize, text: String },
+    NumberTooLarge { line: usize, value: u32 },
+    UnknownLabel { line: usize, label: String },
+    DuplicateLabel { line: usize, label: String },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::AsmError::*;
+
+        match *self {
+            UnknownInstruction { line, ref name } => write!(fmt, "line {}: unknown instruction '{}'", line, name),
+            InvalidOperands { line, ref text } => write!(fmt, "line {}: invalid operands for '{}'", line, text),
+            InvalidNumber { line, ref text } => write!(fmt, "line {}: '{}' is not a number", line, text),
+            NumberTooLarge { line, value } => write!(fmt, "line {}: 0x{:X} is too large here", line, value),
+            UnknownLabel { line, ref label } => write!(fmt, "line {}: no label named '{}'", line, label),
+            DuplicateLabel { line, ref label } => write!(fmt, "line {}: label '{}' is already defined", line, label),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    Reg(u8), // V0 - VF
+    Value(u32), // Numbers and labels
+    I,
+    IndirectI, // [I]
+    Key, // K
+    Delay, // DT
+    Sound, // ST
+    Font, // F
+    LargeFont, // HF
+    Bcd, // B
+    Flags, // R, the RPL user flags
+}
+
+// A line with its comment and label taken off
+struct Line<'a> {
+    number: usize, // Starting from 1, for error messages
+    label: Option<&'a str>,
+    instruction: &'a str,
+}
+
+fn split_line<'a>(number: usize, text: &'a str) -> Line<'a> {
+    let text = match text.find(';') {
+        Some(index) => &text[..index],
+        None => text,
+    };
+
+    let (label, instruction) = match text.find(':') {
+        Some(index) => (Some(text[..index].trim()), &text[index + 1..]),
+        None => (None, text),
+    };
+
+    Line {
+        number: number,
+        label: label,
+        instruction: instruction.trim(),
+    }
+}
+
+// Splits "LD V0, 0x1F" into ("LD", ["V0", "0x1F"])
+fn split_instruction(instruction: &str) -> (String, Vec<&str>) {
+    let (name, rest) = match instruction.find(char::is_whitespace) {
+        Some(index) => (&instruction[..index], instruction[index..].trim()),
+        None => (instruction, ""),
+    };
+
+    let operands = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(|operand| operand.trim()).collect()
+    };
+
+    (name.to_uppercase(), operands)
+}
+
+fn parse_number(line: usize, text: &str) -> Result<u32, AsmError> {
+    let lower = text.to_lowercase();
+
+    let result = if lower.starts_with("0x") {
+        u32::from_str_radix(&lower[2..], 16)
+    } else if lower.starts_with("0b") {
+        u32::from_str_radix(&lower[2..], 2)
+    } else {
+        lower.parse()
+    };
+
+    result.map_err(|_| AsmError::InvalidNumber { line: line, text: text.to_string() })
+}
+
+fn parse_operand(line: usize, text: &str, labels: &HashMap<String, u16>) -> Result<Operand, AsmError> {
+    let upper = text.to_uppercase();
+
+    match upper.as_str() {
+        "I" => return Ok(Operand::I),
+        "[I]" => return Ok(Operand::IndirectI),
+        "K" => return Ok(Operand::Key),
+        "DT" => return Ok(Operand::Delay),
+        "ST" => return Ok(Operand::Sound),
+        "F" => return Ok(Operand::Font),
+        "HF" => return Ok(Operand::LargeFont),
+        "B" => return Ok(Operand::Bcd),
+        "R" => return Ok(Operand::Flags),
+        _ => (),
+    }
+
+    if upper.len() == 2 && upper.starts_with('V') {
+        if let Ok(reg) = u8::from_str_radix(&upper[1..], 16) {
+            return Ok(Operand::Reg(reg));
+        }
+    }
+
+    if text.starts_with(|ch: char| ch.is_digit(10)) {
+        return parse_number(line, text).map(Operand::Value);
+    }
+
+    match labels.get(text) {
+        Some(addr) => Ok(Operand::Value(*addr as u32)),
+        None => Err(AsmError::UnknownLabel { line: line, label: text.to_string() }),
+    }
+}
+
+fn check_size(line: usize, value: u32, max: u32) -> Result<u32, AsmError> {
+    if value > max {
+        return Err(AsmError::NumberTooLarge { line: line, value: value });
+    }
+
+    Ok(value)
+}
+
+fn encode(line: &Line, labels: &HashMap<String, u16>) -> Result<Opcode, AsmError> {
+    use opcode::Opcode::*;
+    use self::Operand::*;
+
+    let (name, operand_text) = split_instruction(line.instruction);
+
+    let mut operands = Vec::new();
+    for text in operand_text.iter() {
+        operands.push(try!(parse_operand(line.number, text, labels)));
+    }
+
+    let number = line.number;
+    let invalid = || AsmError::InvalidOperands { line: number, text: line.instruction.to_string() };
+
+    let addr = |value: u32| check_size(number, value, 0xFFF).map(|value| value as u16);
+    let byte = |value: u32| check_size(number, value, 0xFF).map(|value| value as u8);
+    let nibble = |value: u32| check_size(number, value, 0xF).map(|value| value as u8);
+
+    let reg_mode = |mode: SetRegMode| match operands.as_slice() {
+        [Reg(v_x), Reg(v_y)] => Ok(SetRegToReg { regs: (*v_x, *v_y), mode: mode }),
+        _ => Err(invalid()),
+    };
+
+    let opcode = match (name.as_str(), operands.as_slice()) {
+        ("CLS", []) => ClearScreen,
+        ("RET", []) => Return,
+        ("LOW", []) => LowRes,
+        ("HIGH", []) => HighRes,
+        ("SCD", [Value(lines)]) => ScrollDown(try!(nibble(*lines))),
+        ("SCR", []) => ScrollRight,
+        ("SCL", []) => ScrollLeft,
+        ("EXIT", []) => Exit,
+
+        ("JP", [Value(target)]) => JumpTo { addr: try!(addr(*target)), plus_v0: false },
+        ("JP", [Reg(0), Value(target)]) => JumpTo { addr: try!(addr(*target)), plus_v0: true },
+        ("CALL", [Value(target)]) => Call(try!(addr(*target))),
+
+        ("SE", [Reg(v_x), Value(value)]) => SkipIfRegEqualConst { not_equal: false, reg: *v_x, value: try!(byte(*value)) },
+        ("SNE", [Reg(v_x), Value(value)]) => SkipIfRegEqualConst { not_equal: true, reg: *v_x, value: try!(byte(*value)) },
+        ("SE", [Reg(v_x), Reg(v_y)]) => SkipIfRegsEqual { not_equal: false, regs: (*v_x, *v_y) },
+        ("SNE", [Reg(v_x), Reg(v_y)]) => SkipIfRegsEqual { not_equal: true, regs: (*v_x, *v_y) },
+
+        ("LD", [Reg(v_x), Value(value)]) => SetRegToConst { add: false, reg: *v_x, value: try!(byte(*value)) },
+        ("ADD", [Reg(v_x), Value(value)]) => SetRegToConst { add: true, reg: *v_x, value: try!(byte(*value)) },
+
+        ("LD", [Reg(_), Reg(_)]) => try!(reg_mode(SetRegMode::Copy)),
+        ("OR", _) => try!(reg_mode(SetRegMode::Or)),
+        ("AND", _) => try!(reg_mode(SetRegMode::And)),
+        ("XOR", _) => try!(reg_mode(SetRegMode::Xor)),
+        ("ADD", [Reg(_), Reg(_)]) => try!(reg_mode(SetRegMode::Add)),
+        ("SUB", _) => try!(reg_mode(SetRegMode::Subtract)),
+        ("SUBN", _) => try!(reg_mode(SetRegMode::InverseSubtract)),
+        ("SHR", _) => try!(reg_mode(SetRegMode::ShiftRight)),
+        ("SHL", _) => try!(reg_mode(SetRegMode::ShiftLeft)),
+
+        ("LD", [I, Value(target)]) => SetAddressReg(try!(addr(*target))),
+        ("RND", [Reg(v_x), Value(mask)]) => SetRegToRandom { reg: *v_x, mask: try!(byte(*mask)) },
+        ("DRW", [Reg(v_x), Reg(v_y), Value(rows)]) => DrawSprite { regs: (*v_x, *v_y), rows: try!(nibble(*rows)) },
+
+        ("PLANE", [Value(mask)]) => SelectPlanes(try!(nibble(*mask))),
+
+        ("SKP", [Reg(v_x)]) => SkipIfKeyInRegPressed { not_pressed: false, reg: *v_x },
+        ("SKNP", [Reg(v_x)]) => SkipIfKeyInRegPressed { not_pressed: true, reg: *v_x },
+        ("LD", [Reg(v_x), Key]) => WaitForKeyInReg(*v_x),
+
+        ("LD", [Reg(v_x), Delay]) => SetRegToDelayTimer(*v_x),
+        ("LD", [Delay, Reg(v_x)]) => SetDelayTimerToReg(*v_x),
+        ("LD", [Sound, Reg(v_x)]) => SetSoundTimerToReg(*v_x),
+
+        ("ADD", [I, Reg(v_x)]) => AddRegToAddressReg(*v_x),
+        ("LD", [Font, Reg(v_x)]) => SetAddressRegToCharInReg(*v_x),
+        ("LD", [LargeFont, Reg(v_x)]) => SetAddressRegToLargeCharInReg(*v_x),
+        ("LD", [Bcd, Reg(v_x)]) => RegToBCD(*v_x),
+
+        ("LD", [IndirectI, Reg(v_x)]) => DumpRegsToAddr(*v_x),
+        ("LD", [Reg(v_x), IndirectI]) => LoadRegsFromAddr(*v_x),
+        ("LD", [Flags, Reg(v_x)]) => DumpRegsToFlags(*v_x),
+        ("LD", [Reg(v_x), Flags]) => LoadRegsFromFlags(*v_x),
+
+        _ if MNEMONICS.contains(&name.as_str()) => return Err(invalid()),
+        _ => return Err(AsmError::UnknownInstruction { line: number, name: name.clone() }),
+    };
+
+    Ok(opcode)
+}
+
+// Assembles a program meant to be loaded at `PROGRAM_START`
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let lines: Vec<Line> = source.lines().enumerate().map(|(index, text)| split_line(index + 1, text)).collect();
+
+    // First pass only works out where each label is, since instructions can refer to labels further down
+    let mut labels = HashMap::new();
+    let mut addr = PROGRAM_START;
+
+    for line in lines.iter() {
+        if let Some(label) = line.label {
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(AsmError::DuplicateLabel { line: line.number, label: label.to_string() });
+            }
+        }
+
+        let (name, operands) = split_instruction(line.instruction);
+        if name == "DB" {
+            addr += operands.len() as u16;
+        } else if !name.is_empty() {
+            addr += 2;
+        }
+    }
+
+    let mut bytes = Vec::new();
+
+    for line in lines.iter() {
+        let (name, operands) = split_instruction(line.instruction);
+
+        if name.is_empty() {
+            continue;
+        }
+
+        if name == "DB" {
+            for operand in operands.iter() {
+                let value = try!(parse_number(line.number, operand));
+                bytes.push(try!(check_size(line.number, value, 0xFF)) as u8);
+            }
+
+            continue;
+        }
+
+        let word = try!(encode(line, &labels)).to_u16();
+        bytes.push((word >> 8) as u8);
+        bytes.push(word as u8);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disasm::disassemble;
+    use machine::{Chip8, PROGRAM_START};
+
+    #[test]
+    fn every_opcode_assembles_from_its_disassembly() {
+        for word in 0..0x10000u32 {
+            let word = word as u16;
+
+            if let Ok(opcode) = Opcode::from_u16(word) {
+                let text = opcode.to_string();
+                assert_eq!(assemble(&text).unwrap(), vec![(word >> 8) as u8, word as u8], "{}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn assemble_run_disassemble() {
+        let source = "
+            ; Counts V1 up to 3 in a subroutine, then stores it with BCD
+                LD V1, 0
+            again:
+                CALL count
+                SNE V1, 3
+                JP done
+                JP again
+            count:
+                ADD V1, 1
+                RET
+            done:
+                LD I, digits
+                LD B, V1
+            end:
+                JP end
+            digits: DB 0xFF, 0xFF, 0xFF
+        ";
+        let rom = assemble(source).unwrap();
+
+        let mut chip8 = Chip8::from_bytes(&rom).unwrap();
+        for _ in 0..20 {
+            chip8.step_instruction(None).unwrap();
+        }
+        assert_eq!(chip8.register(1).unwrap(), 3);
+        assert_eq!(&chip8.memory()[0x214..0x217], &[0, 0, 3]);
+
+        // The listing assembles back into the same ROM, with the labels turned into addresses
+        let listing: Vec<String> = disassemble(&rom, PROGRAM_START).into_iter().map(|(_, _, text)| text).collect();
+        assert_eq!(listing[1], "CALL 0x20A");
+        assert_eq!(&listing[listing.len() - 2..], &["DATA 0xFFFF".to_string(), "DATA 0xFF".to_string()]);
+
+        let code_len = listing.len() - 2;
+        let reassembled = assemble(&listing[..code_len].join("\n")).unwrap();
+        assert_eq!(&reassembled[..], &rom[..code_len * 2]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        match assemble("CLS\nJP nowhere") {
+            Err(AsmError::UnknownLabel { line: 2, ref label }) if label == "nowhere" => {},
+            other => panic!("{:?}", other),
+        }
+
+        match assemble("LD V1, 0x100") {
+            Err(AsmError::NumberTooLarge { line: 1, value: 0x100 }) => {},
+            other => panic!("{:?}", other),
+        }
+    }
+}
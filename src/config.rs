@@ -0,0 +1,76 @@
+use std::io::{self, Read};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use toml;
+
+// Physical key names for each CHIP-8 key, in the same QWERTY layout as `frontend::DEFAULT_KEYMAP`
+const DEFAULT_KEYS: [&'static str; 16] = [
+    "X",
+    "1", "2", "3",
+    "Q", "W", "E",
+    "A", "S", "D",
+    "Z", "C",
+    "4", "R", "F", "V",
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref err) => write!(fmt, "{}", err),
+            ConfigError::Parse(ref err) => write!(fmt, "{}", err),
+        }
+    }
+}
+
+// Settings read from a TOML file, any that are left out keep their defaults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub keys: Vec<String>, // Index is the CHIP-8 key, value is the name of the physical key bound to it
+    pub layout: Option<String>, // A preset like "cosmac-vip" to use in place of `keys`
+    pub foreground: String, // Written like "#33FF66"
+    pub background: String,
+    pub scale: u32, // Size in window pixels of a low resolution CHIP-8 pixel
+    pub vsync: bool,
+    pub fps: u32, // Most times a second the window is drawn, 0 for as often as possible
+    pub speed: isize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            keys: DEFAULT_KEYS.iter().map(|key| key.to_string()).collect(),
+            layout: None,
+            foreground: "#FFFFFF".to_string(),
+            background: "#000000".to_string(),
+            scale: 20,
+            vsync: true,
+            fps: 60,
+            speed: 7,
+        }
+    }
+}
+
+impl Config {
+    // A missing file isn't an error, it just means everything is left at its default
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let mut file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(ConfigError::Io(err)),
+        };
+
+        let mut text = String::new();
+        try!(file.read_to_string(&mut text).map_err(ConfigError::Io));
+
+        toml::from_str(&text).map_err(ConfigError::Parse)
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use machine::PROGRAM_START;
+use opcode::{Opcode, SetRegMode};
+
+// Decodes a whole ROM two bytes at a time, with addresses counting up from `start` where it's loaded.
+// Words that aren't valid opcodes (usually sprite data) come back as `DATA` entries with no opcode,
+// so the whole ROM always disassembles.
+pub fn disassemble(bytes: &[u8], start: u16) -> Vec<(u16, Option<Opcode>, String)> {
+    let mut listing = Vec::new();
+
+    for (index, chunk) in bytes.chunks(2).enumerate() {
+        let addr = start + index as u16 * 2;
+
+        // A trailing odd byte can't be an opcode
+        if chunk.len() < 2 {
+            listing.push((addr, None, format!("DATA 0x{:02X}", chunk[0])));
+            continue;
+        }
+
+        let word = (chunk[0] as u16) << 8 | (chunk[1] as u16);
+        match Opcode::from_u16(word) {
+            Ok(opcode) => listing.push((addr, Some(opcode), opcode.to_string())),
+            Err(_) => listing.push((addr, None, format!("DATA 0x{:04X}", word))),
+        }
+    }
+
+    listing
+}
+
+// SUPER-CHIP and XO-CHIP additions, which plain CHIP-8 interpreters won't run
+fn extension(opcode: &Opcode) -> Option<&'static str> {
+    use opcode::Opcode::*;
+
+    match *opcode {
+        LowRes | HighRes | ScrollDown(_) | ScrollRight | ScrollLeft | Exit |
+        SetAddressRegToLargeCharInReg(_) | DumpRegsToFlags(_) | LoadRegsFromFlags(_) => Some("SUPER-CHIP"),
+        DrawSprite { rows: 0, .. } => Some("SUPER-CHIP"),
+        SelectPlanes(_) => Some("XO-CHIP"),
+        _ => None,
+    }
+}
+
+// Opcodes that interpreters disagree on, named after the `Quirks` field that covers them
+fn quirk(opcode: &Opcode) -> Option<&'static str> {
+    use opcode::Opcode::*;
+
+    match *opcode {
+        SetRegToReg { mode: SetRegMode::ShiftLeft, .. } | SetRegToReg { mode: SetRegMode::ShiftRight, .. } => Some("shift_uses_vy"),
+        SetRegToReg { mode: SetRegMode::Or, .. } | SetRegToReg { mode: SetRegMode::And, .. } |
+        SetRegToReg { mode: SetRegMode::Xor, .. } => Some("vf_reset_on_logic"),
+        DumpRegsToAddr(_) | LoadRegsFromAddr(_) => Some("load_store_increments_i"),
+        JumpTo { plus_v0: true, .. } => Some("jump_uses_vx"),
+        AddRegToAddressReg(_) => Some("add_to_i_sets_vf"),
+        DrawSprite { .. } => Some("clip_sprites"),
+        _ => None,
+    }
+}
+
+// A summary of the ROM worked out from its disassembly, without running it.  Sprite data
+// decodes as instructions as often as not, so everything here is a hint rather than a certainty
+pub fn rom_info(bytes: &[u8]) -> String {
+    // Addresses don't show up in the summary, so where it's loaded doesn't matter
+    let listing = disassemble(bytes, PROGRAM_START);
+    let opcodes: Vec<Opcode> = listing.iter().filter_map(|&(_, opcode, _)| opcode).collect();
+
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    let mut extensions = Vec::new();
+    let mut quirks = Vec::new();
+
+    for opcode in opcodes.iter() {
+        *counts.entry(opcode.name()).or_insert(0) += 1;
+
+        if let Some(name) = extension(opcode) {
+            if !extensions.contains(&name) { extensions.push(name); }
+        }
+        if let Some(name) = quirk(opcode) {
+            if !quirks.contains(&name) { quirks.push(name); }
+        }
+    }
+
+    let mut info = String::new();
+    info.push_str(&format!("Size: {} bytes\n", bytes.len()));
+    info.push_str(&format!("Instructions: {} of {} words decode\n", opcodes.len(), listing.len()));
+
+    let platform = if extensions.contains(&"XO-CHIP") {
+        "xochip"
+    } else if extensions.contains(&"SUPER-CHIP") {
+        "schip"
+    } else {
+        "vip or modern"
+    };
+
+    if extensions.is_empty() {
+        info.push_str("Extensions: none\n");
+    } else {
+        info.push_str(&format!("Extensions: {}\n", extensions.join(", ")));
+    }
+    info.push_str(&format!("Platform: {}\n", platform));
+
+    if !quirks.is_empty() {
+        quirks.sort();
+        info.push_str(&format!("Affected by quirks: {}\n", quirks.join(", ")));
+    }
+
+    let mut counts: Vec<(&'static str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    info.push_str("\n");
+    for (name, count) in counts {
+        let percent = count as f64 * 100.0 / opcodes.len() as f64;
+        info.push_str(&format!("{:>10} {:>6.2}% {}\n", count, percent, name));
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_count_from_the_start() {
+        // CLS; JP 0x600; then an odd byte
+        let listing = disassemble(&[0x00, 0xE0, 0x16, 0x00, 0xAB], 0x600);
+        let addrs: Vec<u16> = listing.iter().map(|&(addr, _, _)| addr).collect();
+
+        assert_eq!(addrs, vec![0x600, 0x602, 0x604]);
+        assert_eq!(listing[2].2, "DATA 0xAB");
+    }
+}
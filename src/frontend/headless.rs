@@ -0,0 +1,25 @@
+use super::{Frontend, Palette};
+use chip8::headless::HeadlessFrontend;
+use machine::{Chip8, RuntimeError, Screen};
+
+// The runner itself lives in the library so its tests can use it, this is what --headless adds
+impl Frontend for HeadlessFrontend {
+    fn draw(&mut self, screen: &Screen, _second_plane: &Screen, width: usize, height: usize, _palette: Option<&Palette>) {
+        self.capture(screen, width, height);
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.frame_keys()
+    }
+
+    // There's no debugger to hand a breakpoint to, so the run ends there
+    fn emulate_loop(&mut self, chip8: &mut Chip8) {
+        match self.run(chip8) {
+            Ok(()) => {},
+            Err(err @ RuntimeError::BreakpointHit(_)) | Err(err @ RuntimeError::ConditionMet(_)) => {
+                println!("Stopped at 0x{:03X} after {} frames, {}", chip8.pc(), self.frames_run(), err);
+            },
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
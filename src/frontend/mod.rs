@@ -1,13 +1,101 @@
 
-use machine::Chip8;
+use machine::{Chip8, Screen};
+
+// Colors as (red, green, blue) for each combination of the two XO-CHIP planes, the index has bit 0
+// set for the first plane. Plain CHIP-8 only draws on the first plane, so it's just background and foreground
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub colors: [(u8, u8, u8); 4],
+}
+
+impl Palette {
+    // Pixels that are only or also on the second plane come out orange and yellow
+    pub fn new(background: (u8, u8, u8), foreground: (u8, u8, u8)) -> Palette {
+        Palette {
+            colors: [background, foreground, (255, 102, 0), (255, 204, 0)],
+        }
+    }
+
+    pub fn background(&self) -> (u8, u8, u8) {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> (u8, u8, u8) {
+        self.colors[1]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new((0, 0, 0), (255, 255, 255))
+    }
+}
+
+// Named looks to pick instead of setting the colors one by one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Theme {
+    Classic, // White on black
+    Amber, // Amber and green imitate monochrome monitors, ghosting included
+    Green,
+    Lcd, // Dark pixels on a pale green backlight, like a handheld
+}
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name.to_lowercase().as_str() {
+            "classic" => Some(Theme::Classic),
+            "amber" => Some(Theme::Amber),
+            "green" => Some(Theme::Green),
+            "lcd" => Some(Theme::Lcd),
+            _ => None,
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        match *self {
+            Theme::Classic => Palette::default(),
+            Theme::Amber => Palette::new((0x1A, 0x0F, 0x00), (0xFF, 0xB0, 0x00)),
+            Theme::Green => Palette::new((0x00, 0x14, 0x00), (0x33, 0xFF, 0x33)),
+            Theme::Lcd => Palette::new((0x9B, 0xBC, 0x0F), (0x0F, 0x38, 0x0F)),
+        }
+    }
+
+    pub fn ghosting(&self) -> bool {
+        match *self {
+            Theme::Amber | Theme::Green => true,
+            Theme::Classic | Theme::Lcd => false,
+        }
+    }
+}
+
+// Reads colors written like "#33FF66", the leading '#' is optional
+pub fn parse_color(text: &str) -> Result<(u8, u8, u8), String> {
+    let hex = text.trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|ch| ch.is_digit(16)) {
+        return Err(format!("'{}' is not a color like #RRGGBB", text));
+    }
+
+    let channel = |start: usize| u8::from_str_radix(&hex[start..start + 2], 16).unwrap();
+    Ok((channel(0), channel(2), channel(4)))
+}
 
 pub trait Frontend {
-    fn draw(&mut self, screen: &[[bool; 64]; 32]);
+    // Only the top left `width` x `height` pixels of each plane are in use.
+    // A pixel's color is picked from which of the two planes have it set,
+    // using `palette` if there is one and the frontend's own colors otherwise
+    fn draw(&mut self, screen: &Screen, second_plane: &Screen, width: usize, height: usize, palette: Option<&Palette>);
     fn get_keys(&mut self) -> [bool; 16];
 
-    fn emulate_loop(&mut self, Chip8);
+    // Called whenever the sound timer starts or stops running
+    fn beep(&mut self, _playing: bool) {}
+
+    fn emulate_loop(&mut self, chip8: &mut Chip8);
 }
 
+#[cfg(feature = "sdl")]
 mod sdl;
+mod headless;
 
-pub use self::sdl::SdlFrontend;
+#[cfg(feature = "sdl")]
+pub use self::sdl::{SdlFrontend, KeyLayout, DEFAULT_KEYMAP, keymap_from_names};
+pub use chip8::headless::HeadlessFrontend;
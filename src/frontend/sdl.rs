@@ -1,156 +1,979 @@
 
-use sdl2;
 use sdl2::{Sdl, EventPump};
-use sdl2::rect::Rect;
 use sdl2::keyboard::{Keycode, Scancode};
-use sdl2::video::{Window, WindowPos};
-use sdl2::render::{WindowCanvas};
-use sdl2::keyboard;
-use sdl2::pixels::Color;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::WindowContext;
+use sdl2::rect::Rect;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+use std::cmp;
+use std::fs;
+use std::path::PathBuf;
+use std::collections::VecDeque;
+#[cfg(feature = "screenshot")]
+use std::io;
+#[cfg(feature = "screenshot")]
+use std::path::Path;
+#[cfg(feature = "screenshot")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "record")]
+use std::borrow::Cow;
+
+#[cfg(feature = "screenshot")]
+use png;
+#[cfg(feature = "record")]
+use gif;
+#[cfg(feature = "record")]
+use gif::SetParameter;
+
+use super::{Frontend, Palette};
+use machine::{Chip8, Screen, RuntimeError, Watch, SCREEN_WIDTH, SCREEN_HEIGHT};
+
+const DEFAULT_TONE_HZ: f32 = 440.0;
+
+// Most frames run in one go when emulation falls behind real time
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+// Longest the loop sleeps for between polling events, so input is still picked up promptly
+const MAX_SLEEP_MS: u32 = 5;
+
+// Frames run for every real one while Tab is held
+const FAST_FORWARD_FRAMES: u32 = 4;
+
+// How much brightness an unlit pixel loses each frame when ghosting, so it fades out over 4 frames
+const GHOST_DECAY: u8 = 64;
+
+// Index is the CHIP-8 key, value is the physical key it's bound to.
+// The hex keypad is laid out on the left side of a QWERTY keyboard:
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+pub const DEFAULT_KEYMAP: [Scancode; 16] = [
+    Scancode::X,
+    Scancode::Num1, Scancode::Num2, Scancode::Num3,
+    Scancode::Q, Scancode::W, Scancode::E,
+    Scancode::A, Scancode::S, Scancode::D,
+    Scancode::Z, Scancode::C,
+    Scancode::Num4, Scancode::R, Scancode::F, Scancode::V,
+];
+
+// Each key is labelled with the hex digit it sends, like the keypad in the COSMAC VIP manual,
+// so 0 - 9 are on the number row and A - F are on the letter keys of the same name
+const COSMAC_VIP_KEYMAP: [Scancode; 16] = [
+    Scancode::Num0, Scancode::Num1, Scancode::Num2, Scancode::Num3,
+    Scancode::Num4, Scancode::Num5, Scancode::Num6, Scancode::Num7,
+    Scancode::Num8, Scancode::Num9, Scancode::A, Scancode::B,
+    Scancode::C, Scancode::D, Scancode::E, Scancode::F,
+];
+
+#[derive(Debug, Clone, Copy)]
+pub enum KeyLayout {
+    Qwerty, // `DEFAULT_KEYMAP`
+    CosmacVip,
+    // Scancodes name physical positions rather than what's printed on the key,
+    // so this is the same block of keys as `Qwerty`: 1 2 3 4 / A Z E R / Q S D F / W X C V
+    AzertyFrench,
+    Custom([Scancode; 16]),
+}
+
+impl KeyLayout {
+    // Names as written in the config file's `layout` setting
+    pub fn from_name(name: &str) -> Option<KeyLayout> {
+        match name.to_lowercase().as_str() {
+            "qwerty" => Some(KeyLayout::Qwerty),
+            "cosmac-vip" => Some(KeyLayout::CosmacVip),
+            "azerty" => Some(KeyLayout::AzertyFrench),
+            _ => None,
+        }
+    }
+
+    pub fn keymap(&self) -> [Scancode; 16] {
+        match *self {
+            KeyLayout::Qwerty | KeyLayout::AzertyFrench => DEFAULT_KEYMAP,
+            KeyLayout::CosmacVip => COSMAC_VIP_KEYMAP,
+            KeyLayout::Custom(keymap) => keymap,
+        }
+    }
+}
+
+// Square wave generator fed to SDL's audio thread, see the rust-sdl2 audio examples
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
 
-use super::Frontend;
-use machine::Chip8;
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase <= 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+// Looks up a keymap laid out like `DEFAULT_KEYMAP` from SDL's names for each key
+pub fn keymap_from_names(names: &[String]) -> Result<[Scancode; 16], String> {
+    if names.len() != 16 {
+        return Err(format!("expected 16 keys, got {}", names.len()));
+    }
+
+    let mut keymap = DEFAULT_KEYMAP;
+    for (key, name) in names.iter().enumerate() {
+        keymap[key] = try!(Scancode::from_name(name).ok_or(format!("unknown key '{}'", name)));
+    }
 
-const GRID_SIZE: i32 = 20;
+    Ok(keymap)
+}
 
 pub struct SdlFrontend {
     ctx: Sdl,
     renderer: WindowCanvas,
     events: EventPump,
+    keymap: [Scancode; 16],
+    pressed: [bool; 16], // Keys that went down since the last `get_keys`, so taps shorter than a frame still register
+    scale: u32, // Window pixels per low resolution CHIP-8 pixel
+    pub target_fps: u32, // The loop sleeps rather than going round more often than this, 0 doesn't limit it
+
+    // Scale by whole pixels and letterbox whatever's left over, rather than stretching to fill the window
+    pub integer_scale: bool,
+
+    pub palette: Palette,
+
+    // Phosphor decay, pixels that were recently on fade out instead of vanishing which hides flicker
+    pub ghosting: bool,
+    brightness: [[u8; SCREEN_WIDTH]; SCREEN_HEIGHT],
+
+    pub tone_hz: f32, // Frequency of the beep played while the sound timer is running
+    beeper: Option<AudioDevice<SquareWave>>, // Opened once emulation starts, using `tone_hz`. None without an audio device
+    pub state_path: PathBuf, // Where F7/F8 save and load states on disk
+
+    #[cfg(feature = "record")]
+    recording: Option<Vec<RecordedFrame>>, // Frames captured since F3 started a recording
+
+    // Printed whenever one of their values changes while paused
+    pub watches: Vec<Watch>,
+    watched_values: Vec<u8>, // What was last printed, empty while running
+
+    pub pause_on_focus_loss: bool,
+    auto_paused: bool, // Paused because focus was lost, so getting it back should resume
+
+    pub replay: VecDeque<[bool; 16]>, // Keys for each frame to use in place of the keyboard, see `replay::load`
+    pub input_log: Option<Vec<[bool; 16]>>, // Keys each frame ran with, when recording input for a replay
+
+    // Recent frames, oldest first, that holding Backspace rewinds through
+    history: VecDeque<Chip8>,
+    history_len: usize,
 }
 
 impl SdlFrontend {
-    pub fn new(ctx: Sdl) -> SdlFrontend {
-        let video = ctx.video().unwrap();
-        let window = video.window("Chip8", (GRID_SIZE * 64) as u32, (GRID_SIZE * 32) as u32)
+    // `history_len` is the number of frames kept around for rewinding
+    // Without `vsync`, the only limit on how often frames are presented is `target_fps`
+    pub fn new(ctx: Sdl, history_len: usize, layout: KeyLayout, scale: u32, vsync: bool) -> Result<SdlFrontend, String> {
+        let video = try!(ctx.video());
+        let window = try!(video.window("Chip8", scale * 64, scale * 32)
             .position_centered()
+            .resizable()
             .opengl()
             .build()
-            .unwrap();
+            .map_err(|err| err.to_string()));
+
+        let mut builder = window.into_canvas();
+        if vsync {
+            builder = builder.present_vsync();
+        }
 
-        let renderer = window.into_canvas().build().unwrap();
-        let events = ctx.event_pump().unwrap();
+        let renderer = try!(builder.build().map_err(|err| err.to_string()));
+        let events = try!(ctx.event_pump());
 
-        SdlFrontend {
+        Ok(SdlFrontend {
             ctx: ctx,
             renderer: renderer,
             events: events,
+            keymap: layout.keymap(),
+            pressed: [false; 16],
+            scale: scale,
+            target_fps: 60,
+            integer_scale: false,
+
+            palette: Palette::default(),
+
+            ghosting: false,
+            brightness: [[0; SCREEN_WIDTH]; SCREEN_HEIGHT],
+
+            tone_hz: DEFAULT_TONE_HZ,
+            beeper: None,
+            state_path: PathBuf::from("chip8.state"),
+
+            #[cfg(feature = "record")]
+            recording: None,
+
+            watches: Vec::new(),
+            watched_values: Vec::new(),
+
+            pause_on_focus_loss: true,
+            auto_paused: false,
+
+            replay: VecDeque::new(),
+            input_log: None,
+
+            history: VecDeque::with_capacity(history_len),
+            history_len: history_len,
+        })
+    }
+}
+
+fn to_color((r, g, b): (u8, u8, u8)) -> Color {
+    Color::RGB(r, g, b)
+}
+
+// Mixes `fg` into `bg`, with 255 being entirely `fg`
+fn blend(bg: Color, fg: Color, amount: u8) -> Color {
+    let mix = |from: u8, to: u8| ((from as u32 * (255 - amount as u32) + to as u32 * amount as u32) / 255) as u8;
+    Color::RGB(mix(bg.r, fg.r), mix(bg.g, fg.g), mix(bg.b, fg.b))
+}
+
+impl SdlFrontend {
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    // Resizes the window to match
+    pub fn set_scale(&mut self, scale: u32) -> Result<(), String> {
+        let scale = if scale == 0 { 1 } else { scale };
+
+        try!(self.renderer.window_mut().set_size(scale * 64, scale * 32).map_err(|err| err.to_string()));
+        self.scale = scale;
+
+        Ok(())
+    }
+
+    // What `draw` does, into a texture the caller keeps between frames
+    fn draw_with(&mut self, texture: &mut ScreenTexture, screen: &Screen, second_plane: &Screen, width: usize, height: usize, palette: Option<&Palette>) {
+        let palette = palette.cloned().unwrap_or(self.palette);
+        let colors = [to_color(palette.colors[0]), to_color(palette.colors[1]), to_color(palette.colors[2]), to_color(palette.colors[3])];
+
+        // One texel per CHIP-8 pixel, SDL scales it up to fill the window when it's copied
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let color_index = screen[y][x] as usize | (second_plane[y][x] as usize) << 1;
+
+                // Ghosts top out at half brightness so they're distinguishable from lit pixels
+                let color = if color_index != 0 {
+                    colors[color_index]
+                } else if self.ghosting && self.brightness[y][x] > 0 {
+                    blend(colors[0], colors[1], self.brightness[y][x] / 2)
+                } else {
+                    colors[0]
+                };
+
+                pixels.extend_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+
+        let texture = texture.update(&pixels, width, height);
+
+        let dest = if self.integer_scale {
+            let (window_width, window_height) = self.renderer.output_size().unwrap_or_else(|err| panic!("{}", err));
+            let fit = cmp::max(1, cmp::min(window_width / width as u32, window_height / height as u32));
+            let (dest_width, dest_height) = (fit * width as u32, fit * height as u32);
+
+            // The window can be smaller than a single scale, then the image is cut off evenly on both sides
+            let x = (window_width as i32 - dest_width as i32) / 2;
+            let y = (window_height as i32 - dest_height as i32) / 2;
+            Some(Rect::new(x, y, dest_width, dest_height))
+        } else {
+            None
+        };
+
+        self.renderer.set_draw_color(colors[0]);
+        self.renderer.clear();
+        self.renderer.copy(texture, None, dest).unwrap_or_else(|err| panic!("{}", err));
+        self.renderer.present();
+    }
+
+    // Called once per emulated frame, rather than on every draw, so fading doesn't depend on the render rate
+    fn update_ghosts(&mut self, chip8: &Chip8) {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                self.brightness[y][x] = if chip8.screen()[y][x] || chip8.second_plane()[y][x] {
+                    255
+                } else {
+                    self.brightness[y][x].saturating_sub(GHOST_DECAY)
+                };
+            }
+        }
+    }
+
+    // The replay stands in for the keyboard until it runs out
+    fn frame_keys(&mut self) -> [bool; 16] {
+        let keys = match self.replay.pop_front() {
+            Some(keys) => keys,
+            None => self.get_keys(),
+        };
+
+        if let Some(ref mut log) = self.input_log {
+            log.push(keys);
+        }
+
+        keys
+    }
+
+    fn toggle_watch(&mut self, watch: Watch) {
+        match self.watches.iter().position(|watched| *watched == watch) {
+            Some(index) => {
+                self.watches.remove(index);
+                println!("Stopped watching {}", watch);
+            },
+            None => {
+                self.watches.push(watch);
+                println!("Watching {}", watch);
+            },
+        }
+        self.watched_values.clear();
+    }
+
+    fn print_watches(&mut self, chip8: &Chip8) {
+        let values: Vec<u8> = self.watches.iter().map(|watch| watch.value(chip8)).collect();
+        if values.is_empty() || values == self.watched_values {
+            return;
+        }
+
+        let text: Vec<String> = self.watches.iter().zip(values.iter())
+            .map(|(watch, value)| format!("{} = 0x{:02X}", watch, value))
+            .collect();
+        println!("{}", text.join(", "));
+
+        self.watched_values = values;
+    }
+
+    // Machines without a working audio device still run, `beep` does nothing without one
+    fn open_beeper(&self) -> Result<AudioDevice<SquareWave>, String> {
+        let audio = try!(self.ctx.audio());
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let tone_hz = self.tone_hz;
+        audio.open_playback(None, &desired_spec, |spec| {
+            SquareWave {
+                phase_inc: tone_hz / spec.freq as f32,
+                phase: 0.0,
+                volume: 0.25,
+            }
+        })
+    }
+}
+
+#[cfg(feature = "screenshot")]
+impl SdlFrontend {
+    // Saves the screen as it would be drawn at the current scale, without any ghosting
+    fn screenshot(&self, chip8: &Chip8, path: &Path) -> Result<(), String> {
+        let (width, height) = chip8.screen_size();
+        let cell_size = self.scale as usize * 64 / width;
+        let (image_width, image_height) = (width * cell_size, height * cell_size);
+
+        let mut data = Vec::with_capacity(image_width * image_height * 3);
+        for image_y in 0..image_height {
+            for image_x in 0..image_width {
+                let (x, y) = (image_x / cell_size, image_y / cell_size);
+                let color = to_color(self.palette.colors[chip8.screen()[y][x] as usize | (chip8.second_plane()[y][x] as usize) << 1]);
+
+                data.push(color.r);
+                data.push(color.g);
+                data.push(color.b);
+            }
         }
+
+        let file = try!(fs::File::create(path).map_err(|err| err.to_string()));
+        let mut encoder = png::Encoder::new(io::BufWriter::new(file), image_width as u32, image_height as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = try!(encoder.write_header().map_err(|err| err.to_string()));
+        writer.write_image_data(&data).map_err(|err| err.to_string())
     }
 }
 
-impl Frontend for SdlFrontend {
-    fn draw(&mut self, screen: &[[bool; 64]; 32]) {
-        let mut drawer = &mut self.renderer;
-
-        drawer.set_draw_color(Color::RGB(0, 0, 0));
-        drawer.clear();
-        drawer.set_draw_color(Color::RGB(255, 255, 255));
-
-        for (y, row) in screen.iter().enumerate() {
-            for (x, elem) in row.iter().enumerate() {
-                if *elem {
-                    drawer.fill_rect(Rect::new(
-                        x as i32 * GRID_SIZE,
-                        y as i32 * GRID_SIZE,
-
-                        GRID_SIZE as u32,
-                        GRID_SIZE as u32,
-                    ));
+// A frame's `Palette` indices, one per CHIP-8 pixel.  These are scaled up
+// when the recording is saved, since keeping window sized frames adds up quickly
+#[cfg(feature = "record")]
+struct RecordedFrame {
+    width: usize, // The height follows from the window's 2:1 shape
+    pixels: Vec<u8>,
+}
+
+#[cfg(feature = "record")]
+impl RecordedFrame {
+    fn new(chip8: &Chip8) -> RecordedFrame {
+        let (width, height) = chip8.screen_size();
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(chip8.screen()[y][x] as u8 | (chip8.second_plane()[y][x] as u8) << 1);
+            }
+        }
+
+        RecordedFrame {
+            width: width,
+            pixels: pixels,
+        }
+    }
+}
+
+#[cfg(feature = "record")]
+impl SdlFrontend {
+    // Every frame comes out the size of the window, whichever resolution it was recorded in
+    fn save_recording(&self, frames: &[RecordedFrame], path: &Path) -> Result<(), String> {
+        let (image_width, image_height) = (self.scale as usize * 64, self.scale as usize * 32);
+
+        let mut palette = Vec::new();
+        for index in 0..4 {
+            let color = to_color(self.palette.colors[index]);
+            palette.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+
+        let file = try!(fs::File::create(path).map_err(|err| err.to_string()));
+        let mut encoder = try!(gif::Encoder::new(io::BufWriter::new(file), image_width as u16, image_height as u16, &palette)
+            .map_err(|err| err.to_string()));
+        try!(encoder.set(gif::Repeat::Infinite).map_err(|err| err.to_string()));
+
+        for (number, recorded) in frames.iter().enumerate() {
+            let cell_size = image_width / recorded.width;
+
+            let mut pixels = Vec::with_capacity(image_width * image_height);
+            for image_y in 0..image_height {
+                for image_x in 0..image_width {
+                    pixels.push(recorded.pixels[(image_y / cell_size) * recorded.width + image_x / cell_size]);
                 }
             }
+
+            let mut frame = gif::Frame::default();
+            frame.width = image_width as u16;
+            frame.height = image_height as u16;
+            frame.buffer = Cow::Owned(pixels);
+
+            // GIF delays are in hundredths of a second, so alternate between 1 and 2 to average out to 60 frames a second
+            frame.delay = ((number + 1) * 100 / 60 - number * 100 / 60) as u16;
+
+            try!(encoder.write_frame(&frame).map_err(|err| err.to_string()));
         }
 
-        drawer.present();
+        Ok(())
+    }
+}
+
+// What's about to run, for showing where execution is while paused
+fn next_instruction(chip8: &Chip8) -> String {
+    match chip8.peek_opcode() {
+        Ok(opcode) => opcode.to_string(),
+        Err(err) => err.to_string(),
+    }
+}
+
+// What the screen is drawn into before it's copied to the window.  Its size follows the resolution,
+// so it's only remade when switching between low and high resolution.  It borrows its creator,
+// so `emulate_loop` keeps it rather than the frontend
+struct ScreenTexture<'a> {
+    creator: &'a TextureCreator<WindowContext>,
+    texture: Option<(Texture<'a>, usize, usize)>, // Along with the (width, height) it was made for
+}
+
+impl<'a> ScreenTexture<'a> {
+    fn new(creator: &'a TextureCreator<WindowContext>) -> ScreenTexture<'a> {
+        ScreenTexture {
+            creator: creator,
+            texture: None,
+        }
+    }
+
+    // `pixels` is RGB, 3 bytes for each of `width` x `height` pixels
+    fn update(&mut self, pixels: &[u8], width: usize, height: usize) -> &Texture<'a> {
+        let resized = match self.texture {
+            Some((_, texture_width, texture_height)) => (texture_width, texture_height) != (width, height),
+            None => true,
+        };
+        if resized {
+            let texture = self.creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                .unwrap_or_else(|err| panic!("{}", err));
+            self.texture = Some((texture, width, height));
+        }
+
+        match self.texture {
+            Some((ref mut texture, _, _)) => {
+                texture.update(None, pixels, width * 3).unwrap_or_else(|err| panic!("{}", err));
+                texture
+            },
+            None => unreachable!(),
+        }
+    }
+}
+
+// The register a hex digit key names, like V3 for the 3 key or VA for A
+fn register_watch(keycode: Keycode) -> Option<Watch> {
+    match Watch::from_name(&format!("V{}", keycode.name())) {
+        Some(Watch::Register(reg)) => Some(Watch::Register(reg)),
+        _ => None,
+    }
+}
+
+// Milliseconds since the epoch, so captures taken one after another get their own files
+#[cfg(feature = "screenshot")]
+fn capture_path(extension: &str) -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    PathBuf::from(format!("chip8-{}{:03}.{}", now.as_secs(), now.subsec_millis(), extension))
+}
+
+impl Frontend for SdlFrontend {
+    // Outside of `emulate_loop` there's no texture kept from the last frame, so this makes its own
+    fn draw(&mut self, screen: &Screen, second_plane: &Screen, width: usize, height: usize, palette: Option<&Palette>) {
+        let textures = self.renderer.texture_creator();
+        self.draw_with(&mut ScreenTexture::new(&textures), screen, second_plane, width, height, palette);
     }
 
     fn get_keys(&mut self) -> [bool; 16] {
         let keys = self.events.keyboard_state(); 
         let mut key_arr = [false; 16];
-        
-        key_arr[0x1] = keys.is_scancode_pressed(Scancode::Num1);
-        key_arr[0x2] = keys.is_scancode_pressed(Scancode::Num2);
-        key_arr[0x3] = keys.is_scancode_pressed(Scancode::Num3);
-        key_arr[0xC] = keys.is_scancode_pressed(Scancode::Num4);
-
-        key_arr[0x4] = keys.is_scancode_pressed(Scancode::Q);
-        key_arr[0x5] = keys.is_scancode_pressed(Scancode::W);
-        key_arr[0x6] = keys.is_scancode_pressed(Scancode::E);
-        key_arr[0xD] = keys.is_scancode_pressed(Scancode::R);
-
-        key_arr[0x7] = keys.is_scancode_pressed(Scancode::A);
-        key_arr[0x8] = keys.is_scancode_pressed(Scancode::S);
-        key_arr[0x9] = keys.is_scancode_pressed(Scancode::D);
-        key_arr[0xE] = keys.is_scancode_pressed(Scancode::F);
-
-        key_arr[0xA] = keys.is_scancode_pressed(Scancode::Z);
-        key_arr[0x0] = keys.is_scancode_pressed(Scancode::X);
-        key_arr[0xB] = keys.is_scancode_pressed(Scancode::C);
-        key_arr[0xF] = keys.is_scancode_pressed(Scancode::V);
+
+        for (key, scancode) in self.keymap.iter().enumerate() {
+            key_arr[key] = keys.is_scancode_pressed(*scancode) || self.pressed[key];
+        }
+        self.pressed = [false; 16];
 
         return key_arr;
     }
 
-    fn emulate_loop(&mut self, mut chip8: Chip8) {
+    fn beep(&mut self, playing: bool) {
+        if let Some(ref beeper) = self.beeper {
+            if playing { beeper.resume(); } else { beeper.pause(); }
+        }
+    }
+
+    fn emulate_loop(&mut self, chip8: &mut Chip8) {
         let mut paused = false;
         let mut step = false;
+        let mut step_instruction = false;
+        let mut scale_change: i32 = 0;
+        let mut fast_forward = false;
+        let mut selected_quirk = 0; // Index into `Quirks::flags_mut` that F11 toggles
+        let mut picking_register = false; // O is held down, so hex digits pick a register to watch
+        let mut picked_register = false;
+        let mut toggled_watches = Vec::new(); // Applied once all the events are in
+        let mut redraw = true; // For changes the machine doesn't know about, like the window's scale
+        #[cfg(feature = "screenshot")]
+        let mut take_screenshot = false;
+        #[cfg(feature = "record")]
+        let mut toggle_recording = false;
 
         let mut saved_state: Chip8 = chip8.clone();
 
         let mut timer = self.ctx.timer().unwrap();
-        let mut start_time = timer.ticks();
+
+        // Real time that hasn't been emulated yet, measured in 60ths of a millisecond
+        // so that 60 frames a second divides evenly
+        let mut frame_last = timer.ticks();
+        let mut frame_accum = 0;
+
+        let mut last_draw = timer.ticks();
+
+        let textures = self.renderer.texture_creator();
+        let mut texture = ScreenTexture::new(&textures);
+
+        self.beeper = match self.open_beeper() {
+            Ok(beeper) => Some(beeper),
+            Err(err) => {
+                println!("Sound is off, could not open an audio device: {}", err);
+                None
+            },
+        };
+        let mut beeping = false;
         
         'main: loop {
             for event in self.events.poll_iter() {
-                use sdl2::event::Event;
+                use sdl2::event::{Event, WindowEvent};
 
                 match event {
                     Event::Quit { .. } => break 'main,
 
+                    // A pause from Escape is left alone when focus comes back
+                    Event::Window { win_event: WindowEvent::FocusLost, .. } => {
+                        fast_forward = false; // Tab's key up won't arrive once focus is gone
+                        picking_register = false; // Nor will O's
+
+                        if self.pause_on_focus_loss && !paused {
+                            paused = true;
+                            self.auto_paused = true;
+                        }
+                    },
+                    Event::Window { win_event: WindowEvent::Exposed, .. } |
+                    Event::Window { win_event: WindowEvent::SizeChanged(..), .. } => redraw = true,
+                    Event::Window { win_event: WindowEvent::FocusGained, .. } => {
+                        if self.auto_paused {
+                            paused = false;
+                            self.auto_paused = false;
+                        }
+                    },
+
                     Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                         paused = !paused;
-                        println!("{}", if paused { "Now paused" } else { "Resumed" });
+                        self.auto_paused = false;
+                        if paused {
+                            println!("Now paused at 0x{:X}: {}", chip8.pc(), next_instruction(chip8));
+                        } else {
+                            println!("Resumed");
+                        }
                     },
                     Event::KeyDown { keycode: Some(Keycode::Space), .. } => step = true,
+                    Event::KeyDown { keycode: Some(Keycode::Tab), .. } => fast_forward = true,
+                    Event::KeyUp { keycode: Some(Keycode::Tab), .. } => fast_forward = false,
+                    Event::KeyDown { keycode: Some(Keycode::N), .. } => step_instruction = true,
 
                     Event::KeyDown { keycode: Some(Keycode::I), .. } => println!("\n{:?}\n", chip8),
+                    Event::KeyDown { keycode: Some(Keycode::M), .. } => {
+                        // A few rows either side of whatever I points at
+                        let start = (chip8.address_reg() & !0xF).saturating_sub(0x40);
+                        println!("\n{}", chip8.hex_dump(start, 0x90));
+                    },
+                    // Sets or clears a breakpoint on the instruction that runs next. H is clear of every keypad layout
+                    Event::KeyDown { keycode: Some(Keycode::H), .. } => {
+                        let pc = chip8.pc();
+
+                        if chip8.has_breakpoint(pc) {
+                            chip8.remove_breakpoint(pc);
+                            println!("Breakpoint at 0x{:03X} cleared", pc);
+                        } else {
+                            chip8.add_breakpoint(pc);
+                            println!("Breakpoint set at 0x{:03X}", pc);
+                        }
+                    },
+                    // Tapping O watches or stops watching the byte I points at, holding it down
+                    // and pressing a hex digit does the same for that register instead
+                    Event::KeyDown { keycode: Some(Keycode::O), repeat: false, .. } => {
+                        picking_register = true;
+                        picked_register = false;
+                    },
+                    Event::KeyDown { keycode: Some(keycode), .. } if picking_register && register_watch(keycode).is_some() => {
+                        toggled_watches.push(register_watch(keycode).unwrap());
+                        picked_register = true;
+                    },
+                    Event::KeyUp { keycode: Some(Keycode::O), .. } => {
+                        if picking_register && !picked_register {
+                            toggled_watches.push(Watch::Memory(chip8.address_reg()));
+                        }
+                        picking_register = false;
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::P), .. } if chip8.profiling() => {
+                        println!("\n{}", chip8.profile_report());
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::G), .. } => {
+                        self.ghosting = !self.ghosting;
+                        redraw = true;
+                        println!("Ghosting {}", if self.ghosting { "on" } else { "off" });
+                    },
+
+                    Event::KeyDown { keycode: Some(Keycode::F12), .. } => {
+                        self.integer_scale = !self.integer_scale;
+                        redraw = true;
+                        println!("Integer scaling {}", if self.integer_scale { "on" } else { "off" });
+                    },
+
+                    Event::KeyDown { keycode: Some(Keycode::F10), .. } => {
+                        let quirks = chip8.quirks_mut().flags_mut();
+                        selected_quirk = (selected_quirk + 1) % quirks.len();
+
+                        let (name, ref flag) = quirks[selected_quirk];
+                        println!("Selected quirk {} (currently {}), F11 toggles it", name, if **flag { "on" } else { "off" });
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F11), .. } => {
+                        let mut quirks = chip8.quirks_mut().flags_mut();
+                        let (name, ref mut flag) = quirks[selected_quirk];
+
+                        **flag = !**flag;
+                        println!("Quirk {} now {}", name, if **flag { "on" } else { "off" });
+                    },
+
+                    #[cfg(feature = "screenshot")]
+                    Event::KeyDown { keycode: Some(Keycode::F2), .. } => take_screenshot = true,
+                    #[cfg(feature = "record")]
+                    Event::KeyDown { keycode: Some(Keycode::F3), .. } => toggle_recording = true,
 
                     Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
                         saved_state = chip8.clone();
                         println!("State saved!\n")
                     },
                     Event::KeyDown { keycode: Some(Keycode::F6), .. } => {
-                        chip8 = saved_state.clone();
+                        chip8.restore_from(saved_state.clone());
                         println!("State restored!\n");
                     },
 
+                    Event::KeyDown { keycode: Some(Keycode::F9), .. } => {
+                        chip8.reset();
+                        println!("Program restarted!\n");
+                    },
+
+                    Event::KeyDown { keycode: Some(Keycode::F7), .. } => {
+                        let result = fs::File::create(&self.state_path).and_then(|file| chip8.save_state(file));
+
+                        match result {
+                            Ok(_) => println!("State saved to '{}'!\n", self.state_path.display()),
+                            Err(err) => println!("Could not save state to '{}': {}\n", self.state_path.display(), err),
+                        }
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F8), .. } => {
+                        match fs::File::open(&self.state_path).and_then(Chip8::load_state) {
+                            Ok(state) => {
+                                chip8.restore_from(state);
+                                println!("State loaded from '{}'!\n", self.state_path.display());
+
+                                // Either the program wrote over them, or the state came from another font setup
+                                if !chip8.fonts_intact() {
+                                    println!("The fonts in this state have been written over, digits may come out garbled\n");
+                                }
+                            },
+                            Err(err) => println!("Could not load state from '{}': {}\n", self.state_path.display(), err),
+                        }
+                    },
+
                     Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
-                        if chip8.speed - 1 >= 0 {
-                            chip8.speed -= 1;
-                            println!("Speed: {}", chip8.speed);
+                        if chip8.speed() > 0 {
+                            let speed = chip8.speed() - 1;
+                            chip8.set_speed(speed);
+                            println!("Speed: {}", chip8.speed());
                         }
                     },
 
                     Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
-                        chip8.speed += 1;
-                        println!("Speed: {}", chip8.speed);
+                        let speed = chip8.speed() + 1;
+                        chip8.set_speed(speed);
+                        println!("Speed: {}", chip8.speed());
+                    },
+
+                    // Plus shares a key with equals on most layouts
+                    Event::KeyDown { keycode: Some(Keycode::Equals), .. } |
+                    Event::KeyDown { keycode: Some(Keycode::Plus), .. } |
+                    Event::KeyDown { keycode: Some(Keycode::KpPlus), .. } => scale_change += 1,
+
+                    Event::KeyDown { keycode: Some(Keycode::Minus), .. } |
+                    Event::KeyDown { keycode: Some(Keycode::KpMinus), .. } => scale_change -= 1,
+
+                    Event::KeyDown { scancode: Some(scancode), .. } => {
+                        if let Some(key) = self.keymap.iter().position(|mapped| *mapped == scancode) {
+                            self.pressed[key] = true;
+                        }
                     },
                     
                     _ => (),
                 }
             }
+
+            for watch in toggled_watches.drain(..) {
+                self.toggle_watch(watch);
+            }
             
-            if (!paused && timer.ticks() - start_time > 17) || step {
-                match chip8.cycle(self.get_keys()) {
-                    Ok(_) => (),
-                    Err(err) => panic!("{:?}", err),
+            if scale_change != 0 {
+                let scale = self.scale as i32 + scale_change;
+
+                if scale >= 1 {
+                    match self.set_scale(scale as u32) {
+                        Ok(_) => {
+                            redraw = true;
+                            println!("Scale: {}", self.scale);
+                        },
+                        Err(err) => println!("Could not resize window: {}", err),
+                    }
+                }
+
+                scale_change = 0;
+            }
+
+            #[cfg(feature = "screenshot")]
+            {
+                if take_screenshot {
+                    let path = capture_path("png");
+
+                    match self.screenshot(chip8, &path) {
+                        Ok(_) => println!("Screenshot saved to '{}'!\n", path.display()),
+                        Err(err) => println!("Could not save screenshot to '{}': {}\n", path.display(), err),
+                    }
+
+                    take_screenshot = false;
+                }
+            }
+
+            #[cfg(feature = "record")]
+            {
+                if toggle_recording {
+                    match self.recording.take() {
+                        Some(frames) => {
+                            let path = capture_path("gif");
+
+                            match self.save_recording(&frames, &path) {
+                                Ok(_) => println!("Recorded {} frames to '{}'!\n", frames.len(), path.display()),
+                                Err(err) => println!("Could not save recording to '{}': {}\n", path.display(), err),
+                            }
+                        },
+                        None => {
+                            self.recording = Some(Vec::new());
+                            println!("Recording started, press F3 again to stop\n");
+                        },
+                    }
+
+                    toggle_recording = false;
+                }
+            }
+
+            if step_instruction {
+                // Goes through the replay and input log like a frame does, so a recording stays in step
+                let keys = self.frame_keys();
+                match chip8.step(Some(keys)) {
+                    Ok(_) => println!("Stepped to 0x{:X}: {}", chip8.pc(), next_instruction(chip8)),
+                    Err(RuntimeError::BreakpointHit(addr)) => println!("Breakpoint hit at 0x{:X}: {}", addr, next_instruction(chip8)),
+                    Err(RuntimeError::ConditionMet(condition)) => println!("Stepped to 0x{:X}, {} now holds", chip8.pc(), condition),
+                    Err(RuntimeError::Halted) => {
+                        println!("Program exited");
+                        break 'main;
+                    },
+                    Err(err) => panic!("{}", err),
+                }
+
+                step_instruction = false;
+            }
+
+            let rewinding = self.events.keyboard_state().is_scancode_pressed(Scancode::Backspace);
+
+            let now = timer.ticks();
+            if !paused {
+                frame_accum += (now - frame_last) * 60;
+
+                // The timers keep their own count of real time, so they tick 60 times a second
+                // however many frames run, catch up or get dropped.  Rewinding restores them along
+                // with everything else, and fast forward speeds them up too or delays would hold it back
+                if !rewinding {
+                    let speedup = if fast_forward { FAST_FORWARD_FRAMES } else { 1 };
+                    chip8.advance_timers((now - frame_last) as u64 * 1000 * speedup as u64);
                 }
+            }
+            frame_last = now;
+
+            // After falling far behind (the window being dragged, say) skip ahead
+            // instead of running a burst of frames to catch up
+            frame_accum = cmp::min(frame_accum, MAX_CATCH_UP_FRAMES * 1000);
 
-                start_time = timer.ticks();
-                step = false;
+            let mut frames = frame_accum / 1000;
+            frame_accum %= 1000;
+
+            // Whole frames rather than extra instructions, so timers keep pace with the program
+            if fast_forward && !rewinding {
+                frames *= FAST_FORWARD_FRAMES;
+            }
+
+            // Stepping while paused still runs a whole frame, timers included
+            if step && frames == 0 {
+                frames = 1;
+                chip8.tick_timers();
             }
+            step = false;
+
+            for _ in 0..frames {
+                if rewinding {
+                    if let Some(state) = self.history.pop_back() {
+                        chip8.restore_from(state);
+
+                        // Rewound frames never happened as far as a replay is concerned
+                        if let Some(ref mut log) = self.input_log {
+                            log.pop();
+                        }
+                    }
+                } else {
+                    if self.history_len > 0 {
+                        if self.history.len() == self.history_len {
+                            self.history.pop_front();
+                        }
+                        self.history.push_back(chip8.clone());
+                    }
+
+                    let keys = self.frame_keys();
+                    match chip8.cycle(Some(keys)) {
+                        Ok(_) => (),
+                        Err(RuntimeError::BreakpointHit(addr)) => {
+                            paused = true;
+                            println!("Breakpoint hit at 0x{:X}, now paused\n\n{:?}\n", addr, chip8);
+                        },
+                        Err(RuntimeError::ConditionMet(condition)) => {
+                            paused = true;
+                            println!("{} now holds at 0x{:X}, now paused\n\n{:?}\n", condition, chip8.pc(), chip8);
+                        },
+                        Err(RuntimeError::Halted) => {
+                            println!("Program exited");
+                            break 'main;
+                        },
+                        Err(err) => panic!("{}", err),
+                    }
+                }
+
+                self.update_ghosts(chip8);
+
+                #[cfg(feature = "record")]
+                {
+                    if let Some(ref mut frames) = self.recording {
+                        frames.push(RecordedFrame::new(chip8));
+                    }
+                }
 
-            self.draw(&chip8.screen);
+                if paused {
+                    frame_accum = 0;
+                    break;
+                }
+            }
+
+            if paused {
+                self.print_watches(chip8);
+            } else {
+                self.watched_values.clear();
+            }
+
+            // Timers only change on 60Hz ticks, so beeps start and stop on tick boundaries
+            let should_beep = !paused && chip8.sound_playing();
+            if should_beep != beeping {
+                beeping = should_beep;
+                self.beep(beeping);
+            }
+
+            // Ghosts fade a little every frame, whether or not the screen changed
+            if self.ghosting && frames > 0 {
+                redraw = true;
+            }
+
+            // Nothing changed, so sleep until the next frame is due rather than spinning.
+            // While paused no frame ever comes due, so it's just the longest sleep each time
+            if !chip8.take_dirty().iter().any(|row| *row) && !redraw {
+                let until_next_frame = if paused { MAX_SLEEP_MS } else { (1000 - frame_accum) / 60 };
+                timer.delay(cmp::min(until_next_frame, MAX_SLEEP_MS));
+                continue;
+            }
+            redraw = false;
+
+            let (width, height) = chip8.screen_size();
+            self.draw_with(&mut texture, chip8.screen(), chip8.second_plane(), width, height, None);
+
+            // Sleep off whatever's left of this draw's share of a second
+            if self.target_fps > 0 {
+                let interval = 1000 / self.target_fps;
+                let elapsed = timer.ticks() - last_draw;
+
+                if elapsed < interval {
+                    timer.delay(interval - elapsed);
+                }
+            }
+            last_draw = timer.ticks();
         }
     }
 }
@@ -0,0 +1,136 @@
+use machine::{Chip8, Chip8Event, RuntimeError, Screen, SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// Runs the machine without any display or input devices, a frame at a time, so it can be driven
+// from tests.  The binary's --headless wraps this, so the ROM tests run what users do
+pub struct HeadlessFrontend {
+    key_frames: Vec<[bool; 16]>, // Keys held during each frame, in order
+    frame: usize,
+    frames_to_run: usize,
+
+    screen: Screen,
+    size: (usize, usize), // Part of `screen` in use, as (width, height)
+}
+
+impl HeadlessFrontend {
+    // Once `key_frames` runs out, every key is reported as released
+    pub fn new(key_frames: Vec<[bool; 16]>, frames_to_run: usize) -> HeadlessFrontend {
+        HeadlessFrontend {
+            key_frames: key_frames,
+            frame: 0,
+            frames_to_run: frames_to_run,
+
+            screen: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            size: (64, 32),
+        }
+    }
+
+    pub fn last_screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn last_size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    pub fn frames_run(&self) -> usize {
+        self.frame
+    }
+
+    // The keys held during the frame that runs next
+    pub fn frame_keys(&self) -> [bool; 16] {
+        match self.key_frames.get(self.frame) {
+            Some(keys) => *keys,
+            None => [false; 16],
+        }
+    }
+
+    // Keeps a copy of the screen, the same as drawing it would
+    pub fn capture(&mut self, screen: &Screen, width: usize, height: usize) {
+        self.screen = *screen;
+        self.size = (width, height);
+    }
+
+    // Runs every frame that's left, one `cycle` and one timer tick each.  A program that exits
+    // ends the run early, and so does any error, breakpoints included, after the frame is captured
+    pub fn run(&mut self, chip8: &mut Chip8) -> Result<(), RuntimeError> {
+        while self.frame < self.frames_to_run {
+            let keys = self.frame_keys();
+            let result = chip8.cycle(Some(keys));
+
+            // Every frame is treated as a 60th of a second
+            chip8.tick_timers();
+
+            if chip8.take_events().contains(&Chip8Event::ScreenChanged) {
+                let (width, height) = chip8.screen_size();
+                self.capture(chip8.screen(), width, height);
+            }
+            self.frame += 1;
+
+            match result {
+                Ok(_) => {},
+                Err(RuntimeError::Halted) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Chip8Builder;
+
+    #[test]
+    fn frames_stop_during_key_wait() {
+        // LD V1, K; LD V2, 1; JP 0x204
+        let rom = [0xF1, 0x0A, 0x62, 0x01, 0x12, 0x04];
+        let mut chip8 = Chip8Builder::new().speed(10).rom(&rom).build().unwrap();
+
+        HeadlessFrontend::new(Vec::new(), 10).run(&mut chip8).unwrap();
+        assert_eq!((chip8.pc(), chip8.register(1).unwrap(), chip8.register(2).unwrap()), (0x200, 0, 0));
+
+        // A press and release lets the rest of the program run
+        let mut key_frames = vec![[false; 16]; 2];
+        key_frames[0][4] = true;
+
+        HeadlessFrontend::new(key_frames, 2).run(&mut chip8).unwrap();
+        assert_eq!((chip8.register(1).unwrap(), chip8.register(2).unwrap()), (4, 1));
+    }
+
+    #[test]
+    fn breakpoints_end_the_run() {
+        // LD VF, 1; JP 0x202
+        let rom = [0x6F, 0x01, 0x12, 0x02];
+        let mut chip8 = Chip8Builder::new().rom(&rom).build().unwrap();
+        chip8.add_condition("VF == 1".parse().unwrap());
+
+        let mut frontend = HeadlessFrontend::new(Vec::new(), 10);
+        match frontend.run(&mut chip8) {
+            Err(RuntimeError::ConditionMet(_)) => {},
+            other => panic!("{:?}", other),
+        }
+        assert_eq!((frontend.frames_run(), chip8.pc()), (1, 0x202));
+
+        chip8.add_breakpoint(0x202);
+        let mut frontend = HeadlessFrontend::new(Vec::new(), 10);
+        match frontend.run(&mut chip8) {
+            Err(RuntimeError::BreakpointHit(0x202)) => {},
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(frontend.frames_run(), 1);
+    }
+
+    #[test]
+    fn exiting_keeps_the_last_frame() {
+        // LD I, 0x050 (the 0 glyph); DRW V0, V0, 5; EXIT
+        let rom = [0xA0, 0x50, 0xD0, 0x05, 0x00, 0xFD];
+        let mut chip8 = Chip8Builder::new().rom(&rom).build().unwrap();
+
+        let mut frontend = HeadlessFrontend::new(Vec::new(), 10);
+        frontend.run(&mut chip8).unwrap();
+        assert_eq!(frontend.frames_run(), 1);
+        assert_eq!((frontend.last_size(), frontend.last_screen()[0][0]), ((64, 32), true));
+    }
+}
@@ -0,0 +1,24 @@
+
+// The emulator itself, without any frontends, for embedding it in other programs as well as
+// benchmarking and fuzzing it. The chip8 binary in main.rs builds its frontends on top of this
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_big_array;
+extern crate bincode;
+
+pub mod machine;
+pub mod opcode;
+pub mod disasm;
+pub mod asm;
+pub mod headless;
+
+// Lives here rather than with the binary's frontends so a wasm build can export it
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(test)]
+mod testutil;
+#[cfg(test)]
+mod rom_tests;
@@ -1,17 +1,33 @@
 
-use rand::{thread_rng, Rng};
+use rand::{thread_rng, Rng, SeedableRng, StdRng};
+use serde::{Serializer, Deserializer, Serialize, Deserialize};
+use serde::de::Error;
+use serde_big_array::BigArray;
+use bincode;
 
 use std::io;
 use std::fmt;
+use std::cmp;
+use std::mem;
+use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
 
-use opcode::{Opcode, OpcodeError, SetRegMode};
+use opcode::{Opcode, OpcodeError, OpcodeResult, SetRegMode};
 
-const PROGRAM_START: u16 = 0x200;
+pub const PROGRAM_START: u16 = 0x200;
 const FONT_START: u16 = 0x50;
+const LARGE_FONT_START: u16 = 0xA0; // Right after the small font
 
-const MEMORY_SIZE: usize = 4096;
+pub const MEMORY_SIZE: usize = 4096;
 const REGISTER_COUNT: usize = 16;
 
+// The screen buffer is sized for SUPER-CHIP's high resolution mode,
+// low resolution only uses the top left 64x32 corner of it
+pub const SCREEN_WIDTH: usize = 128;
+pub const SCREEN_HEIGHT: usize = 64;
+
+pub type Screen = [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT];
+
 // Thanks to: http://www.multigesture.net/articles/how-to-write-an-emulator-chip-8-interpreter/
 const FONTMAP: [u8; 80] = [
   0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -32,363 +48,3436 @@ const FONTMAP: [u8; 80] = [
   0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+// SUPER-CHIP's 8x10 digits for FX30, it only had 0 - 9 so A - F are the ones Octo uses
+const LARGE_FONTMAP: [u8; 160] = [
+  0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+  0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+  0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+  0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+  0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+  0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+  0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+  0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+  0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+  0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+  0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+  0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
 #[derive(Debug)]
 pub enum RuntimeError {
     EmptyCallStack,
+    StackOverflow, // A call went deeper than `stack_limit`
     InvalidRegister(u8),
     AddressOutOfBounds(u16),
-    OpcodeErr(OpcodeError),
+    OpcodeErr(u16, OpcodeError), // Address the opcode was fetched from, and why it couldn't be decoded
+    BreakpointHit(u16), // Execution stopped before the instruction at this address
+    ConditionMet(Condition), // Execution stopped right after the instruction that made this true
+    Halted, // The program ran 00FD, the program counter stays on it so it keeps halting
+    Load(LoadError), // `run_headless` couldn't load its program
 }
 
-pub struct Chip8 {
-    pub memory: [u8; MEMORY_SIZE],
-    pub regs: [u8; REGISTER_COUNT], // registers V0 - V15
-    pub address_reg: u16, // register I
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::RuntimeError::*;
 
-    pub pc: u16,
-    pub stack: Vec<u16>,
+        match *self {
+            EmptyCallStack => write!(fmt, "returned with an empty call stack"),
+            StackOverflow => write!(fmt, "calls nested too deeply"),
+            InvalidRegister(reg) => write!(fmt, "no register V{:X}", reg),
+            AddressOutOfBounds(addr) => write!(fmt, "memory access at 0x{:03X} runs past the end of memory", addr),
+            OpcodeErr(addr, ref err) => write!(fmt, "{} at 0x{:03X}", err, addr),
+            BreakpointHit(addr) => write!(fmt, "breakpoint hit at 0x{:03X}", addr),
+            ConditionMet(ref condition) => write!(fmt, "breakpoint condition {} met", condition),
+            Halted => write!(fmt, "program exited"),
+            Load(ref err) => write!(fmt, "could not load the program: {}", err),
+        }
+    }
+}
 
-    pub delay_timer: u16,
-    pub sound_timer: u16,
+// What changed since the last `take_events`, so frontends only have to react to what's new
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip8Event {
+    ScreenChanged,
+    SoundStarted,
+    SoundStopped,
+    WaitingForKey, // FX0A started waiting
+}
 
-    pub screen: [[bool; 64]; 32],
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    ProgramTooLarge(usize, usize), // Size of the program in bytes, and how many fit after its start address
+    BadProgramStart(u16), // Would overlap the fonts or leave no room in memory
+    BadFontSize(usize), // Small fonts are 5 bytes for each of the 16 digits
+    BadFontStart(u16), // Would overlap the large font or run into the program
+}
 
-    // If Some(usize), then put the next key press into the regs[usize]
-    pub awaiting_key: Option<usize>, 
-    pub speed: isize,
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
 }
 
-impl Clone for Chip8 {
-    fn clone(&self) -> Chip8 {
-        let mut memory = [0; 4096];
-        for (offset, byte) in self.memory.iter().enumerate() {
-            memory[offset] = *byte;
+impl fmt::Display for LoadError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref err) => write!(fmt, "{}", err),
+            LoadError::ProgramTooLarge(size, space) => {
+                write!(fmt, "program is {} bytes, but only {} fit in memory", size, space)
+            },
+            LoadError::BadProgramStart(start) => {
+                write!(fmt, "programs can't start at 0x{:03X}, it has to be past the fonts and inside memory", start)
+            },
+            LoadError::BadFontSize(size) => {
+                write!(fmt, "font is {} bytes, it has to be {} bytes", size, FONTMAP.len())
+            },
+            LoadError::BadFontStart(start) => {
+                write!(fmt, "fonts can't start at 0x{:03X}, they have to be clear of the large font and end before the program", start)
+            },
         }
+    }
+}
 
-        let mut regs = [0; 16];
-        for (offset, reg) in self.regs.iter().enumerate() {
-            regs[offset] = *reg;
-        }
+// Behaviors that differ between CHIP-8 interpreters.  ROMs were written against
+// whichever one their author had, so these need to be picked per ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quirks {
+    pub shift_uses_vy: bool, // 8XY6 / 8XYE shift VY into VX instead of shifting VX in place
+    pub load_store_increments_i: bool, // FX55 / FX65 leave I pointing past the last register
+    pub vf_reset_on_logic: bool, // 8XY1 / 8XY2 / 8XY3 zero VF
+    pub jump_uses_vx: bool, // BXNN jumps to XNN + VX rather than NNN + V0
+    pub add_to_i_sets_vf: bool, // FX1E sets VF when I goes past 0xFFF (Amiga interpreter)
+    pub clip_sprites: bool, // DXYN cuts off sprites at the screen edge instead of wrapping them around
+    pub display_wait: bool, // DXYN waits for the vertical blank, so at most one sprite is drawn each frame
+}
 
-        let mut screen = [[false; 64]; 32];
-        for (y, row) in self.screen.iter().enumerate() {
-            for (x, elem) in row.iter().enumerate() {
-                screen[y][x] = *elem; 
-            }
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            vf_reset_on_logic: false,
+            jump_uses_vx: false,
+            add_to_i_sets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
         }
+    }
+}
 
-        Chip8 {
-            memory: memory,
-            regs: regs,
-            address_reg: self.address_reg,
-
-            pc: self.pc,
-            stack: self.stack.clone(),
+impl Quirks {
+    // Every quirk by its field name, for flipping them one at a time to work out what a ROM needs
+    pub fn flags_mut(&mut self) -> Vec<(&'static str, &mut bool)> {
+        vec![
+            ("shift_uses_vy", &mut self.shift_uses_vy),
+            ("load_store_increments_i", &mut self.load_store_increments_i),
+            ("vf_reset_on_logic", &mut self.vf_reset_on_logic),
+            ("jump_uses_vx", &mut self.jump_uses_vx),
+            ("add_to_i_sets_vf", &mut self.add_to_i_sets_vf),
+            ("clip_sprites", &mut self.clip_sprites),
+            ("display_wait", &mut self.display_wait),
+        ]
+    }
+}
 
-            delay_timer: self.delay_timer,
-            sound_timer: self.sound_timer,
+// Interpreters that ROMs are commonly written for, each with the quirks it's known for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    CosmacVip, // The original 1977 interpreter
+    SuperChip, // HP48 SUPER-CHIP 1.1
+    XoChip,
+    Modern, // What most present day emulators do, the same as `Quirks::default()`
+}
 
-            screen: screen,
+impl Platform {
+    // Names as given to --platform
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac-vip" => Some(Platform::CosmacVip),
+            "schip" | "superchip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            "modern" => Some(Platform::Modern),
+            _ => None,
+        }
+    }
 
-            awaiting_key: self.awaiting_key.clone(),
-            speed: self.speed,
+    pub fn quirks(&self) -> Quirks {
+        match *self {
+            Platform::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                vf_reset_on_logic: true,
+                jump_uses_vx: false,
+                add_to_i_sets_vf: false,
+                clip_sprites: true,
+                display_wait: true,
+            },
+            Platform::SuperChip => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                vf_reset_on_logic: false,
+                jump_uses_vx: true,
+                add_to_i_sets_vf: false,
+                clip_sprites: true,
+                display_wait: false,
+            },
+            Platform::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                vf_reset_on_logic: false,
+                jump_uses_vx: false,
+                add_to_i_sets_vf: false,
+                clip_sprites: false,
+                display_wait: false,
+            },
+            Platform::Modern => Quirks::default(),
         }
     }
 }
 
-impl Chip8 {
-    pub fn new() -> Chip8 {
-        let mut chip8 = Chip8 {
-            memory: [0; 4096],
-            regs: [0; 16],
-            address_reg: 0,
-            
-            pc: PROGRAM_START,
-            stack: Vec::new(),
+// Something to keep an eye on while debugging
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watch {
+    Register(u8), // V0 - VF
+    Memory(u16),
+}
 
-            delay_timer: 0,
-            sound_timer: 0,
+impl Watch {
+    // Reads "V3" as a register and "0x300" or "768" as a memory address
+    pub fn from_name(name: &str) -> Option<Watch> {
+        let upper = name.to_uppercase();
 
-            screen: [[false; 64]; 32],
+        if upper.len() == 2 && upper.starts_with('V') {
+            return u8::from_str_radix(&upper[1..], 16).ok().map(Watch::Register);
+        }
 
-            awaiting_key: None,
-            speed: 7,
+        let addr = if upper.starts_with("0X") {
+            u16::from_str_radix(&upper[2..], 16).ok()
+        } else {
+            upper.parse().ok()
         };
 
-        chip8.inject_fontmap();
-        chip8
+        match addr {
+            Some(addr) if (addr as usize) < MEMORY_SIZE => Some(Watch::Memory(addr)),
+            _ => None,
+        }
     }
 
-    pub fn inject_fontmap(&mut self) {
-        for (offset, byte) in FONTMAP.iter().enumerate() {
-            self.memory[FONT_START as usize + offset] = *byte;
+    pub fn value(&self, chip8: &Chip8) -> u8 {
+        match *self {
+            Watch::Register(reg) => chip8.regs[reg as usize],
+            Watch::Memory(addr) => chip8.memory[addr as usize],
         }
     }
+}
 
-    pub fn load_program<R: io::Read>(&mut self, mut program: R) -> io::Result<()> {
-        let mut bytes = Vec::new();
-        try!(program.read_to_end(&mut bytes));
-        
-        for (offset, byte) in bytes.iter().enumerate() {
-            self.memory[offset + PROGRAM_START as usize] = *byte;
+impl fmt::Display for Watch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Watch::Register(reg) => write!(fmt, "V{:X}", reg),
+            Watch::Memory(addr) => write!(fmt, "[0x{:03X}]", addr),
         }
+    }
+}
 
-        Ok(())
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn compare(&self, left: u16, right: u16) -> bool {
+        match *self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
     }
 
-    pub fn cycle(&mut self, keys: [bool; 16]) -> Result<(), RuntimeError> {
-        use self::RuntimeError::*;
+    fn symbol(&self) -> &'static str {
+        match *self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+}
 
-        if let Some(reg) = self.awaiting_key {
-            for (offset, key) in keys.iter().enumerate() {
-                if *key {
-                    self.regs[reg] = offset as u8;
-                    self.awaiting_key = None;
-                }
-            }
+// A breakpoint that fires when the machine's state matches rather than at an address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Register(u8, Comparison, u8), // VX compared against a constant
+    AddressReg(Comparison, u16),
+}
+
+// Reads conditions like "VF == 1" or "I >= 0x300"
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Condition, String> {
+        Condition::parse(text).ok_or_else(|| format!("'{}' isn't a condition like \"VF == 1\" or \"I >= 0x300\"", text))
+    }
+}
+
+impl Condition {
+    fn parse(text: &str) -> Option<Condition> {
+        let parts: Vec<&str> = text.split_whitespace().collect();
+        if parts.len() != 3 {
+            return None;
         }
 
-        for _ in 0..self.speed + 1 {
-            let pc_index = self.pc as usize;
-            let opcode_bytes = (self.memory[pc_index] as u16) << 8 | (self.memory[pc_index + 1] as u16);
+        let comparison = match parts[1] {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return None,
+        };
 
-            let opcode = match Opcode::from_u16(opcode_bytes) {
-                Ok(opcode) => opcode,
-                Err(err) => return Err(OpcodeErr(err)),
-            };
+        let value = if parts[2].to_lowercase().starts_with("0x") {
+            u16::from_str_radix(&parts[2][2..], 16).ok()
+        } else {
+            parts[2].parse().ok()
+        };
+        let value = match value {
+            Some(value) => value,
+            None => return None,
+        };
 
-            self.pc += 2;
-            try!(self.execute_opcode(opcode, keys));
-            //println!("{:X}: {:?}", opcode_bytes, opcode);
+        match Watch::from_name(parts[0]) {
+            Some(Watch::Register(reg)) if value <= 0xFF => Some(Condition::Register(reg, comparison, value as u8)),
+            _ if parts[0].to_uppercase() == "I" => Some(Condition::AddressReg(comparison, value)),
+            _ => None,
         }
+    }
 
-        self.update_timers();
+    pub fn holds(&self, chip8: &Chip8) -> bool {
+        match *self {
+            Condition::Register(reg, comparison, value) => comparison.compare(chip8.regs[reg as usize] as u16, value as u16),
+            Condition::AddressReg(comparison, value) => comparison.compare(chip8.address_reg, value),
+        }
+    }
+}
 
-        Ok(())
+impl fmt::Display for Condition {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Condition::Register(reg, comparison, value) => write!(fmt, "V{:X} {} 0x{:02X}", reg, comparison.symbol(), value),
+            Condition::AddressReg(comparison, value) => write!(fmt, "I {} 0x{:03X}", comparison.symbol(), value),
+        }
     }
+}
 
-    pub fn clear_screen(&mut self) {
-        for row in self.screen.iter_mut() {
-            for col in row.iter_mut() {
-                *col = false;
-            }
+// The delay and sound timers count down at 60Hz, however many instructions run in that time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Timers {
+    pub delay: u16,
+    pub sound: u16,
+    elapsed: u64, // Time since the last tick in 60ths of a microsecond, see `advance`
+}
+
+impl Timers {
+    pub fn new() -> Timers {
+        Timers {
+            delay: 0,
+            sound: 0,
+            elapsed: 0,
         }
     }
 
-    // Wrapping is performed in this function, no need to perform it outside
-    // Returns if a pixel was unset
-    pub fn set_pixel(&mut self, x: usize, y: usize) -> bool {
-        // Equivalent to using the mod operator, but faster
-        let x = x & 63;
-        let y = y & 31;
-    
-        let previous_state = self.screen[y][x];
-        self.screen[y][x] = !self.screen[y][x];
-        
-        // return whether a pixel was previously set and then now unset
-        previous_state
+    pub fn tick(&mut self) {
+        if self.delay > 0 { self.delay -= 1; }
+        if self.sound > 0 { self.sound -= 1; }
     }
 
-    pub fn execute_opcode(&mut self, opcode: Opcode, keys: [bool; 16]) -> Result<(), RuntimeError> {
-        use self::RuntimeError::*;
-        use opcode::Opcode::*;
+    // Ticks once for every 60th of a second that has built up, for frontends
+    // that aren't called exactly 60 times a second. Leftover time carries over to the next call
+    pub fn advance(&mut self, micros: u64) {
+        self.elapsed += micros * 60;
 
-        match opcode { 
-            ClearScreen => self.clear_screen(),
-            Return => {
-                self.pc = match self.stack.pop() {
-                    Some(addr) => addr,
-                    None => return Err(EmptyCallStack),
-                };
-            },
+        while self.elapsed >= 1_000_000 {
+            self.elapsed -= 1_000_000;
+            self.tick();
+        }
+    }
+}
 
-            JumpTo { addr, plus_v0 } => {
-                self.pc = addr;
+const ROW_BYTES: usize = SCREEN_WIDTH / 8;
+
+// What `Chip8::diff` found different between two machines, enough for `apply_delta` to bring
+// one in line with the other.  Memory and the screen are compared a byte and a row at a time,
+// so a frame's worth of changes usually comes to a few dozen bytes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDelta {
+    pub memory: Vec<(u16, u8)>, // Address and new value of each byte that changed
+    pub regs: Vec<(u8, u8)>, // Register number and new value
+    pub address_reg: Option<u16>,
+    pub pc: Option<u16>,
+    pub stack: Option<Vec<u16>>,
+    pub timers: Option<Timers>,
+    pub hires: Option<bool>,
+    pub plane_mask: Option<u8>,
+    pub rpl: Option<[u8; 8]>,
+    pub awaiting_key: Option<Option<usize>>, // Some when it changed, the inner Option is whether FX0A is waiting
+    pub awaited_key_down: Option<Option<u8>>,
+    pub rows: Vec<(u8, u8, [u8; ROW_BYTES])>, // Plane, row and the new row packed 8 pixels to a byte
+}
 
-                if plus_v0 { 
-                    self.pc += self.regs[0] as u16; 
-                }
-            },
-            Call(addr) => {
-                self.stack.push(self.pc);
-                self.pc = addr;
-            },
+impl StateDelta {
+    pub fn is_empty(&self) -> bool {
+        *self == StateDelta::default()
+    }
+}
 
-            SkipIfRegEqualConst { not_equal, reg, value } => {
-                let mut should_jump = self.regs[reg as usize] == value;
+impl Default for StateDelta {
+    fn default() -> StateDelta {
+        StateDelta {
+            memory: Vec::new(),
+            regs: Vec::new(),
+            address_reg: None,
+            pc: None,
+            stack: None,
+            timers: None,
+            hires: None,
+            plane_mask: None,
+            rpl: None,
+            awaiting_key: None,
+            awaited_key_down: None,
+            rows: Vec::new(),
+        }
+    }
+}
 
-                if not_equal {
-                    should_jump = !should_jump; // Effectively computes self.regs[reg] != value
-                }
+fn pack_row(row: &[bool; SCREEN_WIDTH]) -> [u8; ROW_BYTES] {
+    let mut packed = [0; ROW_BYTES];
+    for (x, pixel) in row.iter().enumerate() {
+        if *pixel {
+            packed[x / 8] |= 0x80 >> (x % 8);
+        }
+    }
 
-                if should_jump {
-                    self.pc += 2;
-                }
-            },
-            SkipIfRegsEqual { not_equal, regs: (v_x, v_y) } => {
-                let mut should_jump = self.regs[v_x as usize] == self.regs[v_y as usize];
+    packed
+}
 
-                if not_equal {
-                    should_jump = !should_jump;
-                }
+#[derive(Serialize, Deserialize)]
+pub struct Chip8 {
+    #[serde(with = "BigArray")]
+    memory: [u8; MEMORY_SIZE],
+    regs: [u8; REGISTER_COUNT], // registers V0 - V15
+    address_reg: u16, // register I
 
-                if should_jump {
-                    self.pc += 2;
-                }
-            },
+    font_start: u16, // Where FX29 looks for the small font, see `set_font`
+    font: Vec<u8>, // Written back to `font_start` on reset
 
-            SetRegToConst { add, reg, value } => {
-                if add {
-                    let value = (self.regs[reg as usize] as u32 + value as u32) & 255;
-                    self.regs[reg as usize] = value as u8;
-                } else {
-                    self.regs[reg as usize] = value;
-                }
-            },
-            SetRegToReg { regs: (v_x, v_y), mode } => {
-                let v_x = v_x as usize;
-                let v_y = v_y as usize;
+    pc: u16,
+    stack: Vec<u16>,
+    stack_limit: usize, // Most calls that can be nested, the original interpreter allowed 16
 
-                match mode {
-                    SetRegMode::Copy => self.regs[v_x] = self.regs[v_y],
-                    
-                    SetRegMode::Or => self.regs[v_x] |= self.regs[v_y],
-                    SetRegMode::And => self.regs[v_x] &= self.regs[v_y],
-                    SetRegMode::Xor => self.regs[v_x] ^= self.regs[v_y],
+    timers: Timers,
 
-                    SetRegMode::Add => {
-                        self.regs[0xF] = 0;
+    rpl: [u8; 8], // SUPER-CHIP's RPL user flags, they're left alone by `reset` like they were on the HP48
+    program_start: u16, // Where `load_bytes` puts the program and `pc` starts, `PROGRAM_START` unless changed
+    program_len: usize, // Bytes loaded at `program_start` by `load_bytes`, the code that `watch_code_writes` covers
 
-                        let mut reg_value = self.regs[v_x] as usize + self.regs[v_y] as usize;
-                        if reg_value > 255 {
-                            reg_value -= 256;
-                            self.regs[0xF] = 1;
-                        }
+    #[serde(serialize_with = "serialize_screen", deserialize_with = "deserialize_screen")]
+    screen: Screen,
+    #[serde(serialize_with = "serialize_screen", deserialize_with = "deserialize_screen")]
+    second_plane: Screen, // XO-CHIP's second bit plane, `screen` doubles as the first
+    plane_mask: u8, // Which planes draw and clear opcodes affect, bit 0 is `screen`
+    hires: bool, // SUPER-CHIP 128x64 mode
 
-                        self.regs[v_x] = reg_value as u8;
-                    },
-                    SetRegMode::Subtract | SetRegMode::InverseSubtract => {
-                        self.regs[0xF] = 1;
+    // If Some(usize), then put the next key press into the regs[usize]
+    awaiting_key: Option<usize>, 
+    awaited_key_down: Option<u8>, // Key pressed while awaiting, it's stored once it's released again
+    #[serde(skip)]
+    keys: [bool; 16], // Held keys, from the last `cycle` or `step` or from `set_keys`
+    speed: isize,
+    cycles: u64, // Total instructions executed
+    last_collisions: u32, // Pixels the last DXYN turned off, VF only says whether there were any
+    steps_since_tick: isize, // Instructions run through `step` since the timers last ticked
+    #[serde(skip)]
+    waiting_for_vblank: bool, // A sprite was drawn under `Quirks::display_wait`, nothing more runs this frame
+
+    // Debugging aids, these aren't part of the machine state so they're left out of save states
+    #[serde(skip)]
+    breakpoints: HashSet<u16>,
+    #[serde(skip)]
+    resume_at: Option<u16>, // Breakpoint that was just hit, it shouldn't fire again when execution resumes
+    #[serde(skip)]
+    conditions: Vec<(Condition, bool)>, // Along with whether each held after the last instruction
+    #[serde(skip)]
+    profiling: bool, // Count how often each opcode runs, see `profile_report`
+    #[serde(skip)]
+    profile: HashMap<&'static str, u64>,
+    #[serde(skip)]
+    watch_code_writes: bool, // Note writes into the loaded program, see `code_write_report`
+    #[serde(skip)]
+    code_writes: HashMap<(u16, u16), u64>, // How often each instruction wrote to each address, keyed by (writer, address)
+    #[serde(skip)]
+    trace: Option<Box<dyn io::Write>>, // Gets a line for every instruction executed, see `write_trace`
+
+    // What `take_events` last reported
+    #[serde(skip)]
+    screen_changed: bool,
+    #[serde(skip)]
+    sound_was_on: bool,
+    #[serde(skip)]
+    was_waiting_for_key: bool,
+
+    // Rows changed since the last `take_dirty`, a freshly loaded state needs a full redraw
+    #[serde(skip, default = "all_dirty")]
+    dirty: [bool; SCREEN_HEIGHT],
+
+    quirks: Quirks,
+
+    // Save states get a freshly seeded generator, see `seed_rng` for reproducible runs
+    #[serde(skip, default = "random_rng")]
+    rng: StdRng,
+}
 
-                        let mut reg_value = if mode == SetRegMode::Subtract {
-                            self.regs[v_x] as isize - self.regs[v_y] as isize
-                        } else { // Must be InverseSubtract
-                            self.regs[v_y] as isize - self.regs[v_x] as isize
-                        };
+fn all_dirty() -> [bool; SCREEN_HEIGHT] {
+    [true; SCREEN_HEIGHT]
+}
 
-                        if reg_value < 0 {
-                            reg_value += 256;
-                            self.regs[0xF] = 0;
-                        }
+fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::from_seed(&[seed as usize, (seed >> 32) as usize][..])
+}
 
-                        self.regs[v_x] = reg_value as u8;
-                    },
-                        
-                    // v_y is ignored for the shift opcodes, not sure why
-                    SetRegMode::ShiftLeft => {
-                        self.regs[0xF] = self.regs[v_x] & 128;
+fn random_rng() -> StdRng {
+    seeded_rng(thread_rng().gen())
+}
 
-                        self.regs[v_x] <<= 1;
-                    },
-                    SetRegMode::ShiftRight => {
-                        self.regs[0xF] = self.regs[v_x] & 0x1;
-                        self.regs[v_x] >>= 1;
-                    }
-                }
-            },
+// serde can't handle the nested screen array directly, so it goes through as a list of rows
+fn serialize_screen<S: Serializer>(screen: &Screen, serializer: S) -> Result<S::Ok, S::Error> {
+    let rows: Vec<&[bool]> = screen.iter().map(|row| &row[..]).collect();
+    rows.serialize(serializer)
+}
 
-            SetAddressReg(addr) => self.address_reg = addr,
-            SetRegToRandom { reg, mask } => {
-                let rand: u8 = thread_rng().gen();
-                self.regs[reg as usize] = rand & mask;
-            },
+fn deserialize_screen<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Screen, D::Error> {
+    let rows: Vec<Vec<bool>> = try!(Deserialize::deserialize(deserializer));
+    if rows.len() != SCREEN_HEIGHT || rows.iter().any(|row| row.len() != SCREEN_WIDTH) {
+        return Err(D::Error::custom("screen must be 128x64"));
+    }
 
-            DrawSprite { regs: (v_x, v_y), rows } => {
-                let x = self.regs[v_x as usize] as usize;
-                let y = self.regs[v_y as usize] as usize;
+    let mut screen = [[false; SCREEN_WIDTH]; SCREEN_HEIGHT];
+    for (y, row) in rows.iter().enumerate() {
+        screen[y].copy_from_slice(row);
+    }
 
-                self.regs[0xF] = 0;
+    Ok(screen)
+}
 
-                for row in 0..rows {
-                    let sprite_slice = self.memory[(self.address_reg + row as u16) as usize];
-                    
-                    for col in 0..8 {
-                        if (sprite_slice & (128 >> col)) != 0 {
-                            if self.set_pixel(x + col as usize, y + row as usize) {
-                                self.regs[0xF] = 1;
-                            }
-                        }
-                    }
-                }
-            },
+// "PC OPCODE MNEMONIC [V0 .. VF] I" for an instruction that's about to run
+fn write_trace_line(trace: &mut dyn io::Write, pc: u16, opcode_bytes: u16, opcode: &Opcode,
+                    regs: &[u8], address_reg: u16) -> io::Result<()> {
+    try!(write!(trace, "{:03X} {:04X} {:<16} [", pc, opcode_bytes, opcode.to_string()));
+    for (index, reg) in regs.iter().enumerate() {
+        try!(write!(trace, "{}{:02X}", if index == 0 { "" } else { " " }, reg));
+    }
 
-            SetRegToDelayTimer(reg) => self.regs[reg as usize] = self.delay_timer as u8,
+    writeln!(trace, "] I={:03X}", address_reg)
+}
 
-            SetDelayTimerToReg(reg) => self.delay_timer = self.regs[reg as usize] as u16,
-            SetSoundTimerToReg(reg) => self.sound_timer = self.regs[reg as usize] as u16,
+impl Clone for Chip8 {
+    // Can't be derived because of `trace`, but every array here is Copy
+    fn clone(&self) -> Chip8 {
+        Chip8 {
+            memory: self.memory,
+            regs: self.regs,
+            address_reg: self.address_reg,
 
-            AddRegToAddressReg(reg) => self.address_reg += self.regs[reg as usize] as u16,
-            SetAddressRegToCharInReg(reg) => {
-                let ch = self.regs[reg as usize];
-                self.address_reg = FONT_START + ch as u16 * 5;
-            },
+            font_start: self.font_start,
+            font: self.font.clone(),
 
-            WaitForKeyInReg(reg) => self.awaiting_key = Some(reg as usize),
-            SkipIfKeyInRegPressed { not_pressed, reg } => {
-                let mut should_jump = keys[self.regs[reg as usize] as usize];     
+            pc: self.pc,
+            stack: self.stack.clone(),
+            stack_limit: self.stack_limit,
 
-                if not_pressed {
-                    should_jump = !should_jump;
-                }
+            timers: self.timers,
 
-                if should_jump {
-                    self.pc += 2;
-                }
-            },
+            rpl: self.rpl,
+            program_start: self.program_start,
+            program_len: self.program_len,
 
-            // See http://en.wikipedia.org/wiki/Binary-coded_decimal
-            // n mod 10 => Gets the ones digit out of a number
-            RegToBCD(reg) => {
-                let number = self.regs[reg as usize];
+            screen: self.screen,
+            second_plane: self.second_plane,
+            plane_mask: self.plane_mask,
+            hires: self.hires,
 
-                let hundreds_digit = number / 100;
-                let tens_digit = (number / 10) % 10; // Dividing by ten slides the tens digit into the ones digit
-                let ones_digit = number % 10;
+            awaiting_key: self.awaiting_key.clone(),
+            awaited_key_down: self.awaited_key_down,
+            keys: self.keys,
+            speed: self.speed,
+            cycles: self.cycles,
+            last_collisions: self.last_collisions,
+            steps_since_tick: self.steps_since_tick,
+            waiting_for_vblank: self.waiting_for_vblank,
+
+            breakpoints: self.breakpoints.clone(),
+            resume_at: self.resume_at,
+            conditions: self.conditions.clone(),
+            profiling: self.profiling,
+            profile: self.profile.clone(),
+            watch_code_writes: self.watch_code_writes,
+            code_writes: HashMap::new(), // These can get large, and `restore_from` takes them from the running machine anyway
+            trace: None, // Copies are for saving and rewinding, only the original should trace
+
+            screen_changed: self.screen_changed,
+            sound_was_on: self.sound_was_on,
+            was_waiting_for_key: self.was_waiting_for_key,
+
+            dirty: all_dirty(), // Whatever copy gets restored (when rewinding, say) has to be drawn in full
+
+            quirks: self.quirks,
+            rng: self.rng.clone(),
+        }
+    }
+}
 
-                self.memory[(self.address_reg) as usize] = hundreds_digit;
-                self.memory[(self.address_reg + 1) as usize] = tens_digit;
-                self.memory[(self.address_reg + 2) as usize] = ones_digit;
-            },
+// For setting up a machine in one go, anything that's left alone keeps the default from `Chip8::new`
+pub struct Chip8Builder {
+    quirks: Quirks,
+    speed: Option<isize>,
+    seed: Option<u64>,
+    stack_limit: Option<usize>,
+    program_start: Option<u16>,
+    rom: Option<Vec<u8>>,
+}
 
-            DumpRegsToAddr(reg) => {
-                for cur_reg in 0..(reg + 1) {
-                    self.memory[(self.address_reg + cur_reg as u16)  as usize] = self.regs[cur_reg as usize];  
-                }
-            },
-            LoadRegsFromAddr(reg) => {
-                for cur_reg in 0..(reg + 1) {
-                    self.regs[cur_reg as usize] = self.memory[(self.address_reg + cur_reg as u16) as usize];
-                }
-            }
+impl Chip8Builder {
+    pub fn new() -> Chip8Builder {
+        Chip8Builder {
+            quirks: Quirks::default(),
+            speed: None,
+            seed: None,
+            stack_limit: None,
+            program_start: None,
+            rom: None,
         }
+    }
 
-        Ok(())
+    // Replaces any quirks set before it, and is replaced by any set after
+    pub fn platform(self, platform: Platform) -> Chip8Builder {
+        self.quirks(platform.quirks())
     }
 
-    fn update_timers(&mut self) {
-        if self.delay_timer > 0 { self.delay_timer -= 1; }
-        if self.sound_timer > 0 { self.sound_timer -= 1; }
+    pub fn quirks(mut self, quirks: Quirks) -> Chip8Builder {
+        self.quirks = quirks;
+        self
     }
-}
 
-impl fmt::Debug for Chip8 {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        try!(writeln!(fmt, "Program Counter: 0x{:X} ({})", self.pc, self.pc));
-        try!(writeln!(fmt, "Address Register: 0x{:X} ({})", self.address_reg, self.address_reg));
-        try!(writeln!(fmt, "Stack: {:?}", self.stack));
-        try!(writeln!(fmt, "Delay Timer: {}", self.delay_timer));
-        try!(writeln!(fmt, "Sound Timer: {}", self.sound_timer));
+    pub fn speed(mut self, speed: isize) -> Chip8Builder {
+        self.speed = Some(speed);
+        self
+    }
 
-        write!(fmt, "Register Contents: {:?}", self.regs)
+    pub fn seed(mut self, seed: u64) -> Chip8Builder {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn stack_limit(mut self, stack_limit: usize) -> Chip8Builder {
+        self.stack_limit = Some(stack_limit);
+        self
+    }
+
+    // The ETI-660 loaded programs at 0x600 instead of 0x200
+    pub fn program_start(mut self, start: u16) -> Chip8Builder {
+        self.program_start = Some(start);
+        self
+    }
+
+    pub fn rom(mut self, bytes: &[u8]) -> Chip8Builder {
+        self.rom = Some(bytes.to_vec());
+        self
+    }
+
+    pub fn build(self) -> Result<Chip8, LoadError> {
+        let mut chip8 = Chip8::with_quirks(self.quirks);
+
+        if let Some(speed) = self.speed {
+            chip8.set_speed(speed);
+        }
+
+        if let Some(seed) = self.seed {
+            chip8.seed_rng(seed);
+        }
+
+        if let Some(stack_limit) = self.stack_limit {
+            chip8.stack_limit = stack_limit;
+        }
+
+        if let Some(start) = self.program_start {
+            try!(chip8.set_program_start(start));
+        }
+
+        if let Some(ref rom) = self.rom {
+            try!(chip8.load_bytes(rom));
+        }
+
+        Ok(chip8)
+    }
+}
+
+// Runs `rom` for a number of instructions with the same keys held throughout, for embedding
+// and tests that only care about the end result.  Instructions spent waiting on FX0A count
+// without doing anything.  FX0A takes a key once it's released, so a held key is let go for
+// the one step that hands it over, otherwise the wait would never end
+pub fn run_headless(rom: &[u8], instructions: u64, keys: [bool; 16]) -> Result<Chip8, RuntimeError> {
+    let mut chip8 = try!(Chip8Builder::new().rom(rom).build().map_err(RuntimeError::Load));
+
+    for _ in 0..instructions {
+        let keys = if chip8.awaited_key_down.is_some() { [false; 16] } else { keys };
+        try!(chip8.step(Some(keys)));
+    }
+
+    Ok(chip8)
+}
+
+impl Chip8 {
+    pub fn new() -> Chip8 {
+        let mut chip8 = Chip8 {
+            memory: [0; 4096],
+            regs: [0; 16],
+            address_reg: 0,
+
+            font_start: FONT_START,
+            font: FONTMAP.to_vec(),
+            
+            pc: PROGRAM_START,
+            stack: Vec::new(),
+            stack_limit: 16,
+
+            timers: Timers::new(),
+
+            rpl: [0; 8],
+            program_start: PROGRAM_START,
+            program_len: 0,
+
+            screen: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            second_plane: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            plane_mask: 1,
+            hires: false,
+
+            awaiting_key: None,
+            awaited_key_down: None,
+            keys: [false; 16],
+            speed: 7,
+            cycles: 0,
+            last_collisions: 0,
+            steps_since_tick: 0,
+            waiting_for_vblank: false,
+
+            breakpoints: HashSet::new(),
+            resume_at: None,
+            conditions: Vec::new(),
+            profiling: false,
+            profile: HashMap::new(),
+            watch_code_writes: false,
+            code_writes: HashMap::new(),
+            trace: None,
+
+            screen_changed: true,
+            sound_was_on: false,
+            was_waiting_for_key: false,
+
+            dirty: all_dirty(),
+
+            quirks: Quirks::default(),
+            rng: random_rng(),
+        };
+
+        chip8.inject_fontmap();
+        chip8
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.quirks = quirks;
+        chip8
+    }
+
+    pub fn with_platform(platform: Platform) -> Chip8 {
+        Chip8::with_quirks(platform.quirks())
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // Jumping anywhere an opcode can't be fetched from is refused
+    pub fn set_pc(&mut self, addr: u16) -> Result<(), RuntimeError> {
+        try!(self.check_bounds(addr, 2));
+        self.pc = addr;
+        Ok(())
+    }
+
+    pub fn address_reg(&self) -> u16 {
+        self.address_reg
+    }
+
+    pub fn set_address_reg(&mut self, addr: u16) {
+        self.address_reg = addr;
+    }
+
+    pub fn register(&self, reg: u8) -> Result<u8, RuntimeError> {
+        self.regs.get(reg as usize).cloned().ok_or(RuntimeError::InvalidRegister(reg))
+    }
+
+    pub fn set_register(&mut self, reg: u8, value: u8) -> Result<(), RuntimeError> {
+        match self.regs.get_mut(reg as usize) {
+            Some(current) => {
+                *current = value;
+                Ok(())
+            },
+            None => Err(RuntimeError::InvalidRegister(reg)),
+        }
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn second_plane(&self) -> &Screen {
+        &self.second_plane
+    }
+
+    pub fn speed(&self) -> isize {
+        self.speed
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn quirks_mut(&mut self) -> &mut Quirks {
+        &mut self.quirks
+    }
+
+    // Negative speeds would never run an instruction, so they're treated as 0
+    pub fn set_speed(&mut self, speed: isize) {
+        self.speed = cmp::max(speed, 0);
+    }
+
+    pub fn delay(&self) -> u16 {
+        self.timers.delay
+    }
+
+    pub fn sound(&self) -> u16 {
+        self.timers.sound
+    }
+
+    pub fn sound_playing(&self) -> bool {
+        self.timers.sound > 0
+    }
+
+    // Makes CXNN produce the same numbers every run, for reproducing bugs and testing
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = seeded_rng(seed);
+    }
+
+    // Puts the machine back to how it was right after loading the program, which stays in memory.
+    // Configuration like quirks, speed and breakpoints is kept as well
+    pub fn reset(&mut self) {
+        self.regs = [0; REGISTER_COUNT];
+        self.address_reg = 0;
+
+        self.pc = self.program_start;
+        self.stack.clear();
+
+        self.timers = Timers::new();
+
+        self.clear_planes(0b11);
+        self.plane_mask = 1;
+        self.hires = false;
+
+        self.awaiting_key = None;
+        self.awaited_key_down = None;
+        self.cycles = 0;
+        self.last_collisions = 0;
+        self.steps_since_tick = 0;
+        self.waiting_for_vblank = false;
+        self.resume_at = None;
+        self.profile.clear();
+        self.code_writes.clear();
+
+        self.inject_fontmap();
+    }
+
+    pub fn inject_fontmap(&mut self) {
+        for (offset, byte) in self.font.iter().enumerate() {
+            self.memory[self.font_start as usize + offset] = *byte;
+        }
+
+        for (offset, byte) in LARGE_FONTMAP.iter().enumerate() {
+            self.memory[LARGE_FONT_START as usize + offset] = *byte;
+        }
+    }
+
+    // Replaces the small font with `data`, 5 bytes for each character 0 - F, stored at `start`.
+    // The default font stays in memory where it was, only FX29 stops pointing at it.
+    // Fonts have to end before `program_start`, so loading a program can't overwrite them
+    pub fn set_font(&mut self, start: u16, data: &[u8]) -> Result<(), LoadError> {
+        if data.len() != FONTMAP.len() {
+            return Err(LoadError::BadFontSize(data.len()));
+        }
+
+        let end = start as usize + data.len();
+        let large = LARGE_FONT_START as usize;
+        let overlaps_large = (start as usize) < large + LARGE_FONTMAP.len() && end > large;
+        if overlaps_large || end > self.program_start as usize {
+            return Err(LoadError::BadFontStart(start));
+        }
+
+        self.font_start = start;
+        self.font = data.to_vec();
+        self.inject_fontmap();
+
+        Ok(())
+    }
+
+    // Where FX29 points I for a character
+    pub fn font_address(&self, digit: u8) -> u16 {
+        self.font_start + digit as u16 * 5
+    }
+
+    // Where FX30 points I for a character
+    pub fn large_font_address(&self, digit: u8) -> u16 {
+        LARGE_FONT_START + digit as u16 * 10
+    }
+
+    // Whether both fonts are still in memory as they were put there.  Programs are free to
+    // write over them, and anything drawing digits afterwards will come out garbled
+    pub fn fonts_intact(&self) -> bool {
+        let small = self.font_start as usize;
+        let large = LARGE_FONT_START as usize;
+
+        self.memory[small..small + self.font.len()] == self.font[..] &&
+            self.memory[large..large + LARGE_FONTMAP.len()] == LARGE_FONTMAP[..]
+    }
+
+    pub fn program_start(&self) -> u16 {
+        self.program_start
+    }
+
+    // Moves where the next `load_bytes` puts the program, and the program counter along with it.
+    // Anything already loaded stays where it was
+    pub fn set_program_start(&mut self, start: u16) -> Result<(), LoadError> {
+        let fonts_end = cmp::max(self.font_start as usize + self.font.len(), LARGE_FONT_START as usize + LARGE_FONTMAP.len());
+        if (start as usize) < fonts_end || start as usize >= MEMORY_SIZE {
+            return Err(LoadError::BadProgramStart(start));
+        }
+
+        self.program_start = start;
+        self.pc = start;
+
+        Ok(())
+    }
+
+    // A fresh machine with `rom` already loaded
+    pub fn from_bytes(rom: &[u8]) -> Result<Chip8, LoadError> {
+        let mut chip8 = Chip8::new();
+        try!(chip8.load_bytes(rom));
+
+        Ok(chip8)
+    }
+
+    pub fn load_program<R: io::Read>(&mut self, mut program: R) -> Result<(), LoadError> {
+        let mut bytes = Vec::new();
+        try!(program.read_to_end(&mut bytes));
+
+        self.load_bytes(&bytes)
+    }
+
+    pub fn load_bytes(&mut self, bytes: &[u8]) -> Result<(), LoadError> {
+        let start = self.program_start as usize;
+        if bytes.len() > MEMORY_SIZE - start {
+            return Err(LoadError::ProgramTooLarge(bytes.len(), MEMORY_SIZE - start));
+        }
+        
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+        self.program_len = bytes.len();
+
+        Ok(())
+    }
+
+    // Writes a binary snapshot of the whole machine, which `load_state` can read back
+    pub fn save_state<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        bincode::serialize_into(&mut writer, self).map_err(|err| {
+            io::Error::new(io::ErrorKind::Other, err)
+        })
+    }
+
+    pub fn load_state<R: io::Read>(mut reader: R) -> io::Result<Chip8> {
+        bincode::deserialize_from(&mut reader).map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })
+    }
+
+    // Replaces the machine with `state`, a save state or an earlier copy, but keeps the breakpoints,
+    // conditions, profiling and trace set up on this one. Save states and copies leave some of those out
+    pub fn restore_from(&mut self, mut state: Chip8) {
+        state.breakpoints = mem::replace(&mut self.breakpoints, HashSet::new());
+        state.resume_at = None;
+
+        // Whether each condition held has to be worked out again for the new state
+        state.conditions = self.conditions.iter().map(|&(condition, _)| (condition, condition.holds(&state))).collect();
+
+        state.profiling = self.profiling;
+        state.profile = mem::replace(&mut self.profile, HashMap::new());
+        state.watch_code_writes = self.watch_code_writes;
+        state.code_writes = mem::replace(&mut self.code_writes, HashMap::new());
+        state.trace = self.trace.take();
+
+        *self = state;
+    }
+
+    // The changes that would turn this machine into `other`.  Only what a program can change is
+    // compared, configuration like quirks, speed and breakpoints is left out
+    pub fn diff(&self, other: &Chip8) -> StateDelta {
+        // Only ever Some when it changed
+        fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+            if old != new { Some(new.clone()) } else { None }
+        }
+
+        let mut delta = StateDelta::default();
+
+        for (addr, (old, new)) in self.memory.iter().zip(other.memory.iter()).enumerate() {
+            if old != new {
+                delta.memory.push((addr as u16, *new));
+            }
+        }
+
+        for (reg, (old, new)) in self.regs.iter().zip(other.regs.iter()).enumerate() {
+            if old != new {
+                delta.regs.push((reg as u8, *new));
+            }
+        }
+
+        delta.address_reg = changed(&self.address_reg, &other.address_reg);
+        delta.pc = changed(&self.pc, &other.pc);
+        delta.stack = changed(&self.stack, &other.stack);
+        delta.timers = changed(&self.timers, &other.timers);
+        delta.hires = changed(&self.hires, &other.hires);
+        delta.plane_mask = changed(&self.plane_mask, &other.plane_mask);
+        delta.rpl = changed(&self.rpl, &other.rpl);
+        delta.awaiting_key = changed(&self.awaiting_key, &other.awaiting_key);
+        delta.awaited_key_down = changed(&self.awaited_key_down, &other.awaited_key_down);
+
+        let planes = [(&self.screen, &other.screen), (&self.second_plane, &other.second_plane)];
+        for (plane, &(old, new)) in planes.iter().enumerate() {
+            for y in 0..SCREEN_HEIGHT {
+                if old[y][..] != new[y][..] {
+                    delta.rows.push((plane as u8, y as u8, pack_row(&new[y])));
+                }
+            }
+        }
+
+        delta
+    }
+
+    // Applies what `diff` found, the screen changes are marked dirty and reported like they would be by drawing
+    pub fn apply_delta(&mut self, delta: &StateDelta) {
+        for &(addr, value) in delta.memory.iter() {
+            if let Some(byte) = self.memory.get_mut(addr as usize) {
+                *byte = value;
+            }
+        }
+
+        for &(reg, value) in delta.regs.iter() {
+            if let Some(current) = self.regs.get_mut(reg as usize) {
+                *current = value;
+            }
+        }
+
+        if let Some(address_reg) = delta.address_reg { self.address_reg = address_reg; }
+        if let Some(pc) = delta.pc { self.pc = pc; }
+        if let Some(ref stack) = delta.stack { self.stack = stack.clone(); }
+        if let Some(timers) = delta.timers { self.timers = timers; }
+        if let Some(plane_mask) = delta.plane_mask { self.plane_mask = plane_mask; }
+        if let Some(rpl) = delta.rpl { self.rpl = rpl; }
+        if let Some(awaiting_key) = delta.awaiting_key { self.awaiting_key = awaiting_key; }
+        if let Some(awaited_key_down) = delta.awaited_key_down { self.awaited_key_down = awaited_key_down; }
+
+        if let Some(hires) = delta.hires {
+            self.hires = hires;
+            self.dirty = all_dirty();
+        }
+        if delta.hires.is_some() || !delta.rows.is_empty() {
+            self.screen_changed = true;
+        }
+
+        for &(plane, y, ref packed) in delta.rows.iter() {
+            let y = y as usize;
+            if plane > 1 || y >= SCREEN_HEIGHT {
+                continue;
+            }
+
+            let row = &mut self.plane_mut(plane as usize)[y];
+            for x in 0..SCREEN_WIDTH {
+                row[x] = packed[x / 8] & (0x80 >> (x % 8)) != 0;
+            }
+            self.dirty[y] = true;
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.profiling
+    }
+
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiling = profiling;
+    }
+
+    pub fn watching_code_writes(&self) -> bool {
+        self.watch_code_writes
+    }
+
+    pub fn set_watch_code_writes(&mut self, watch: bool) {
+        self.watch_code_writes = watch;
+    }
+
+    // Replaces any trace that was being written, None stops tracing
+    pub fn set_trace(&mut self, trace: Option<Box<dyn io::Write>>) {
+        self.trace = trace;
+    }
+
+    // Conditions only fire when they go from false to true, so execution can resume while one still holds
+    pub fn add_condition(&mut self, condition: Condition) {
+        let holds = condition.holds(self);
+        self.conditions.push((condition, holds));
+    }
+
+    pub fn remove_condition(&mut self, condition: Condition) {
+        self.conditions.retain(|&(existing, _)| existing != condition);
+    }
+
+    fn check_conditions(&mut self) -> Result<(), RuntimeError> {
+        let mut met = None;
+
+        for index in 0..self.conditions.len() {
+            let (condition, held) = self.conditions[index];
+            let holds = condition.holds(self);
+
+            if holds && !held && met.is_none() {
+                met = Some(condition);
+            }
+            self.conditions[index].1 = holds;
+        }
+
+        match met {
+            Some(condition) => Err(RuntimeError::ConditionMet(condition)),
+            None => Ok(()),
+        }
+    }
+
+    // `keys` replaces the held keys before running, None keeps whatever `set_keys`,
+    // `press_key` and `release_key` left them as
+    pub fn cycle(&mut self, keys: Option<[bool; 16]>) -> Result<(), RuntimeError> {
+        let keys = self.update_keys(keys);
+        self.check_awaited_key(keys);
+
+        for _ in 0..self.speed + 1 {
+            try!(self.execute_next(keys));
+
+            // The rest of the frame goes to waiting for the vertical blank
+            if self.waiting_for_vblank {
+                self.waiting_for_vblank = false;
+                break;
+            }
+
+            // Nothing else runs until `check_awaited_key` sees a key at the start of a later cycle
+            if self.awaiting_key.is_some() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Executes a single instruction.  The timers are ticked every `speed + 1` steps,
+    // so they keep the same pace relative to instructions as they have under `cycle`
+    pub fn step(&mut self, keys: Option<[bool; 16]>) -> Result<(), RuntimeError> {
+        let keys = self.update_keys(keys);
+        self.check_awaited_key(keys);
+        try!(self.execute_next(keys));
+
+        // A sprite drawn under `Quirks::display_wait` ends the frame early, same as in `cycle`
+        self.steps_since_tick += 1;
+        if self.steps_since_tick > self.speed || self.waiting_for_vblank {
+            self.steps_since_tick = 0;
+            self.waiting_for_vblank = false;
+            self.tick_timers();
+        }
+
+        Ok(())
+    }
+
+    // Executes a single instruction and hands back what it was, for debuggers to show.  Unlike
+    // `step` the timers are left alone, `tick_timers` or `advance_timers` have to be called separately.
+    // While FX0A is waiting for a key, that's the instruction that comes back
+    pub fn step_instruction(&mut self, keys: Option<[bool; 16]>) -> Result<Opcode, RuntimeError> {
+        let keys = self.update_keys(keys);
+        self.check_awaited_key(keys);
+
+        let opcode = try!(self.execute_next(keys));
+        self.waiting_for_vblank = false;
+
+        Ok(opcode)
+    }
+
+    fn update_keys(&mut self, keys: Option<[bool; 16]>) -> [bool; 16] {
+        if let Some(keys) = keys {
+            self.keys = keys;
+        }
+
+        self.keys
+    }
+
+    pub fn keys(&self) -> [bool; 16] {
+        self.keys
+    }
+
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        self.keys = keys;
+    }
+
+    // Keys past 0xF don't exist and are ignored
+    pub fn press_key(&mut self, key: u8) {
+        if let Some(held) = self.keys.get_mut(key as usize) {
+            *held = true;
+        }
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        if let Some(held) = self.keys.get_mut(key as usize) {
+            *held = false;
+        }
+    }
+
+    // FX0A waits for a key to be pressed and then released, so a key that's still
+    // held down doesn't also get picked up by whatever the program does next.
+    // The program counter stays on the FX0A until then
+    fn check_awaited_key(&mut self, keys: [bool; 16]) {
+        if let Some(reg) = self.awaiting_key {
+            match self.awaited_key_down {
+                Some(key) => {
+                    if !keys[key as usize] {
+                        self.regs[reg] = key;
+                        self.awaiting_key = None;
+                        self.awaited_key_down = None;
+                        self.pc += 2;
+                    }
+                },
+                None => self.awaited_key_down = keys.iter().position(|key| *key).map(|key| key as u8),
+            }
+        }
+    }
+
+    // Fetches, decodes and executes the instruction at `pc`, returning it
+    fn execute_next(&mut self, keys: [bool; 16]) -> Result<Opcode, RuntimeError> {
+        use self::RuntimeError::*;
+
+        // FX0A halts everything until a key comes in through `check_awaited_key`
+        if self.awaiting_key.is_some() {
+            return self.peek_opcode().map_err(|err| OpcodeErr(self.pc, err));
+        }
+
+        // Calling `cycle` again after a breakpoint carries on from where it stopped
+        if self.breakpoints.contains(&self.pc) && self.resume_at != Some(self.pc) {
+            self.resume_at = Some(self.pc);
+            return Err(BreakpointHit(self.pc));
+        }
+        self.resume_at = None;
+
+        // A jump to 0xFFF would otherwise read the opcode's second byte from past the end of memory
+        try!(self.check_bounds(self.pc, 2));
+
+        let pc_index = self.pc as usize;
+        let opcode_bytes = (self.memory[pc_index] as u16) << 8 | (self.memory[pc_index + 1] as u16);
+
+        let opcode = match Opcode::from_u16(opcode_bytes) {
+            Ok(opcode) => opcode,
+            Err(err) => return Err(OpcodeErr(self.pc, err)),
+        };
+
+        if self.trace.is_some() {
+            self.write_trace(opcode_bytes, &opcode);
+        }
+
+        self.pc += 2;
+        self.cycles += 1;
+        try!(self.execute_opcode(opcode, keys));
+
+        try!(self.check_conditions());
+        Ok(opcode)
+    }
+
+    // Decodes the instruction `execute_next` would run, without running it
+    pub fn peek_opcode(&self) -> OpcodeResult {
+        let pc = self.pc as usize;
+        if pc + 2 > MEMORY_SIZE {
+            return Err(OpcodeError::PastEndOfMemory(self.pc));
+        }
+
+        Opcode::from_u16((self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16)
+    }
+
+    // Tracing stops if the writer fails, rather than failing the whole program
+    fn write_trace(&mut self, opcode_bytes: u16, opcode: &Opcode) {
+        let (pc, regs, address_reg) = (self.pc, self.regs, self.address_reg);
+
+        let result = match self.trace {
+            Some(ref mut trace) => write_trace_line(&mut **trace, pc, opcode_bytes, opcode, &regs, address_reg),
+            None => return,
+        };
+
+        if result.is_err() {
+            self.trace = None;
+        }
+    }
+
+    // Width and height of the part of `screen` in use for the current resolution
+    pub fn screen_size(&self) -> (usize, usize) {
+        if self.hires {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        } else {
+            (64, 32)
+        }
+    }
+
+    // The first plane at the current resolution packed 8 pixels to a byte, row by row with the
+    // leftmost pixel in the top bit, so 256 bytes in low resolution and 1024 in high
+    pub fn screen_to_bytes(&self) -> Vec<u8> {
+        let (width, height) = self.screen_size();
+        let mut bytes = vec![0; width * height / 8];
+
+        for y in 0..height {
+            for x in 0..width {
+                if self.screen[y][x] {
+                    bytes[(y * width + x) / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+
+        bytes
+    }
+
+    // The inverse of `screen_to_bytes`. Returns false, leaving the screen alone, if there
+    // aren't exactly enough bytes for the current resolution
+    pub fn screen_from_bytes(&mut self, bytes: &[u8]) -> bool {
+        let (width, height) = self.screen_size();
+        if bytes.len() != width * height / 8 {
+            return false;
+        }
+
+        let screen = self.plane_mut(0);
+        for y in 0..height {
+            for x in 0..width {
+                screen[y][x] = bytes[(y * width + x) / 8] & (0x80 >> (x % 8)) != 0;
+            }
+        }
+        self.dirty = all_dirty();
+
+        true
+    }
+
+    // XO-CHIP's planes are numbered 0 and 1, `plane_mask` has a bit set for each selected one
+    fn plane_selected(&self, plane: usize) -> bool {
+        self.plane_mask & (1 << plane) != 0
+    }
+
+    // Everything that writes to the screen goes through here
+    fn plane_mut(&mut self, plane: usize) -> &mut Screen {
+        self.screen_changed = true;
+        if plane == 0 { &mut self.screen } else { &mut self.second_plane }
+    }
+
+    fn clear_planes(&mut self, mask: u8) {
+        for plane in 0..2 {
+            if mask & (1 << plane) == 0 {
+                continue;
+            }
+
+            for row in self.plane_mut(plane).iter_mut() {
+                for col in row.iter_mut() {
+                    *col = false;
+                }
+            }
+
+            self.dirty = all_dirty();
+        }
+    }
+
+    // Only clears the selected planes
+    pub fn clear_screen(&mut self) {
+        let mask = self.plane_mask;
+        self.clear_planes(mask);
+    }
+
+    // Scrolling works in pixels of the current resolution on the selected planes, and whatever
+    // scrolls off the edge is lost while the vacated rows or columns are cleared
+    pub fn scroll_down(&mut self, lines: usize) {
+        let (width, height) = self.screen_size();
+
+        for plane in 0..2 {
+            if !self.plane_selected(plane) {
+                continue;
+            }
+
+            let screen = self.plane_mut(plane);
+            for y in (0..height).rev() {
+                for x in 0..width {
+                    screen[y][x] = if y >= lines { screen[y - lines][x] } else { false };
+                }
+            }
+        }
+
+        self.dirty = all_dirty();
+    }
+
+    pub fn scroll_right(&mut self, cols: usize) {
+        let (width, height) = self.screen_size();
+
+        for plane in 0..2 {
+            if !self.plane_selected(plane) {
+                continue;
+            }
+
+            let screen = self.plane_mut(plane);
+            for y in 0..height {
+                for x in (0..width).rev() {
+                    screen[y][x] = if x >= cols { screen[y][x - cols] } else { false };
+                }
+            }
+        }
+
+        self.dirty = all_dirty();
+    }
+
+    pub fn scroll_left(&mut self, cols: usize) {
+        let (width, height) = self.screen_size();
+
+        for plane in 0..2 {
+            if !self.plane_selected(plane) {
+                continue;
+            }
+
+            let screen = self.plane_mut(plane);
+            for y in 0..height {
+                for x in 0..width {
+                    screen[y][x] = if x + cols < width { screen[y][x + cols] } else { false };
+                }
+            }
+        }
+
+        self.dirty = all_dirty();
+    }
+
+    // Wrapping is performed in this function, no need to perform it outside
+    // Returns if a pixel was unset
+    pub fn set_pixel(&mut self, plane: usize, x: usize, y: usize) -> bool {
+        // Both resolutions are powers of two, so this is equivalent to using the mod operator, but faster
+        let (width, height) = self.screen_size();
+        let x = x & (width - 1);
+        let y = y & (height - 1);
+
+        self.dirty[y] = true;
+        let screen = self.plane_mut(plane);
+    
+        let previous_state = screen[y][x];
+        screen[y][x] = !screen[y][x];
+        
+        // return whether a pixel was previously set and then now unset
+        previous_state
+    }
+
+    // Opcodes only have a nibble for each register, but `execute_opcode` can be handed anything
+    fn reg(&self, reg: u8) -> Result<u8, RuntimeError> {
+        match self.regs.get(reg as usize) {
+            Some(value) => Ok(*value),
+            None => Err(RuntimeError::InvalidRegister(reg)),
+        }
+    }
+
+    fn set_reg(&mut self, reg: u8, value: u8) -> Result<(), RuntimeError> {
+        match self.regs.get_mut(reg as usize) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            },
+            None => Err(RuntimeError::InvalidRegister(reg)),
+        }
+    }
+
+    // Makes sure `len` bytes starting at `start` all lie within memory
+    // Out of range accesses are reported as an error rather than wrapped around
+    fn check_bounds(&self, start: u16, len: usize) -> Result<(), RuntimeError> {
+        if start as usize + len > MEMORY_SIZE {
+            return Err(RuntimeError::AddressOutOfBounds(start));
+        }
+
+        Ok(())
+    }
+
+    pub fn execute_opcode(&mut self, opcode: Opcode, keys: [bool; 16]) -> Result<(), RuntimeError> {
+        use self::RuntimeError::*;
+        use opcode::Opcode::*;
+
+        if self.profiling {
+            *self.profile.entry(opcode.name()).or_insert(0) += 1;
+        }
+
+        match opcode { 
+            ClearScreen => self.clear_screen(),
+
+            // Switching resolution leaves the old contents at the wrong scale, so start fresh
+            HighRes => {
+                self.hires = true;
+                self.clear_planes(0b11);
+            },
+            LowRes => {
+                self.hires = false;
+                self.clear_planes(0b11);
+            },
+
+            ScrollDown(lines) => self.scroll_down(lines as usize),
+            ScrollRight => self.scroll_right(4),
+            ScrollLeft => self.scroll_left(4),
+            Exit => {
+                self.pc -= 2;
+                return Err(RuntimeError::Halted);
+            },
+
+            Return => {
+                self.pc = match self.stack.pop() {
+                    Some(addr) => addr,
+                    None => return Err(EmptyCallStack),
+                };
+            },
+
+            JumpTo { addr, plus_v0 } => {
+                self.pc = addr;
+
+                if plus_v0 { 
+                    // With the quirk, the high nibble of the address doubles as the register
+                    let reg = if self.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0 };
+                    self.pc += self.regs[reg] as u16; 
+                }
+            },
+            Call(addr) => {
+                if self.stack.len() >= self.stack_limit {
+                    return Err(StackOverflow);
+                }
+
+                self.stack.push(self.pc);
+                self.pc = addr;
+            },
+
+            SkipIfRegEqualConst { not_equal, reg, value } => {
+                let mut should_jump = try!(self.reg(reg)) == value;
+
+                if not_equal {
+                    should_jump = !should_jump; // Effectively computes self.regs[reg] != value
+                }
+
+                if should_jump {
+                    self.pc += 2;
+                }
+            },
+            SkipIfRegsEqual { not_equal, regs: (v_x, v_y) } => {
+                let mut should_jump = try!(self.reg(v_x)) == try!(self.reg(v_y));
+
+                if not_equal {
+                    should_jump = !should_jump;
+                }
+
+                if should_jump {
+                    self.pc += 2;
+                }
+            },
+
+            SetRegToConst { add, reg, value } => {
+                let value = if add { try!(self.reg(reg)).wrapping_add(value) } else { value };
+                try!(self.set_reg(reg, value));
+            },
+            SetRegToReg { regs: (v_x, v_y), mode } => {
+                let x = try!(self.reg(v_x));
+                let y = try!(self.reg(v_y));
+
+                // VF is always written last, so the flag wins when VF is also the destination
+                match mode {
+                    SetRegMode::Copy => try!(self.set_reg(v_x, y)),
+                    
+                    SetRegMode::Or | SetRegMode::And | SetRegMode::Xor => {
+                        let value = match mode {
+                            SetRegMode::Or => x | y,
+                            SetRegMode::And => x & y,
+                            _ => x ^ y, // Must be Xor
+                        };
+                        try!(self.set_reg(v_x, value));
+
+                        if self.quirks.vf_reset_on_logic {
+                            self.regs[0xF] = 0;
+                        }
+                    },
+
+                    SetRegMode::Add => {
+                        let (value, carry) = x.overflowing_add(y);
+
+                        try!(self.set_reg(v_x, value));
+                        self.regs[0xF] = carry as u8;
+                    },
+                    SetRegMode::Subtract | SetRegMode::InverseSubtract => {
+                        let (value, borrow) = if mode == SetRegMode::Subtract {
+                            x.overflowing_sub(y)
+                        } else { // Must be InverseSubtract
+                            y.overflowing_sub(x)
+                        };
+
+                        // VF is set when there's no borrow
+                        try!(self.set_reg(v_x, value));
+                        self.regs[0xF] = !borrow as u8;
+                    },
+                        
+                    // v_y is ignored for the shift opcodes unless the quirk says otherwise
+                    SetRegMode::ShiftLeft => {
+                        let value = if self.quirks.shift_uses_vy { y } else { x };
+
+                        try!(self.set_reg(v_x, value << 1));
+                        self.regs[0xF] = value >> 7;
+                    },
+                    SetRegMode::ShiftRight => {
+                        let value = if self.quirks.shift_uses_vy { y } else { x };
+
+                        try!(self.set_reg(v_x, value >> 1));
+                        self.regs[0xF] = value & 0x1;
+                    }
+                }
+            },
+
+            SetAddressReg(addr) => self.address_reg = addr,
+            SetRegToRandom { reg, mask } => {
+                let rand: u8 = self.rng.gen();
+                try!(self.set_reg(reg, rand & mask));
+            },
+
+            DrawSprite { regs: (v_x, v_y), rows } => {
+                let (width, height) = self.screen_size();
+                self.waiting_for_vblank = self.quirks.display_wait;
+
+                // The starting position always wraps, clipping only applies to the rest of the sprite
+                let x = try!(self.reg(v_x)) as usize % width;
+                let y = try!(self.reg(v_y)) as usize % height;
+
+                // SUPER-CHIP draws a 16x16 sprite, two bytes per row, for DXY0 in high resolution
+                let (sprite_width, sprite_rows) = if rows == 0 && self.hires { (16, 16) } else { (8, rows as usize) };
+                let sprite_len = sprite_rows * sprite_width / 8;
+
+                // With both XO-CHIP planes selected, the second plane's sprite follows the first's
+                let plane_count = (0..2).filter(|plane| self.plane_selected(*plane)).count();
+
+                // A sprite running past the end of memory is an error, not wrapped
+                try!(self.check_bounds(self.address_reg, sprite_len * plane_count));
+
+                // VF ends up 1 only if this sprite turned off a pixel that was on, in any row or plane and
+                // wherever it wrapped to.  Clipped pixels are never drawn, so they can't collide.
+                // It's cleared only after VX and VY are read, as either could be VF
+                self.regs[0xF] = 0;
+                self.last_collisions = 0;
+
+                let mut sprite_addr = self.address_reg as usize;
+                for plane in 0..2 {
+                    if !self.plane_selected(plane) {
+                        continue;
+                    }
+
+                    for row in 0..sprite_rows {
+                        // Left aligned in 16 bits, so both sprite widths are read from the top bit down
+                        let sprite_slice = if sprite_width == 16 {
+                            (self.memory[sprite_addr + row * 2] as u16) << 8 | self.memory[sprite_addr + row * 2 + 1] as u16
+                        } else {
+                            (self.memory[sprite_addr + row] as u16) << 8
+                        };
+                        
+                        for col in 0..sprite_width {
+                            let (pixel_x, pixel_y) = (x + col, y + row);
+
+                            if self.quirks.clip_sprites && (pixel_x >= width || pixel_y >= height) {
+                                continue;
+                            }
+
+                            if (sprite_slice & (0x8000 >> col)) != 0 {
+                                if self.set_pixel(plane, pixel_x, pixel_y) {
+                                    self.regs[0xF] = 1;
+                                    self.last_collisions += 1;
+                                }
+                            }
+                        }
+                    }
+
+                    sprite_addr += sprite_len;
+                }
+            },
+
+            SelectPlanes(mask) => self.plane_mask = mask,
+
+            SetRegToDelayTimer(reg) => {
+                let delay = self.timers.delay as u8;
+                try!(self.set_reg(reg, delay));
+            },
+
+            SetDelayTimerToReg(reg) => self.timers.delay = try!(self.reg(reg)) as u16,
+            SetSoundTimerToReg(reg) => self.timers.sound = try!(self.reg(reg)) as u16,
+
+            AddRegToAddressReg(reg) => {
+                let value = try!(self.reg(reg));
+                let sum = self.address_reg as u32 + value as u32;
+                self.address_reg = self.address_reg.wrapping_add(value as u16);
+
+                if self.quirks.add_to_i_sets_vf {
+                    self.regs[0xF] = if sum > 0x0FFF { 1 } else { 0 };
+                }
+            },
+            SetAddressRegToCharInReg(reg) => {
+                let ch = try!(self.reg(reg));
+                self.address_reg = self.font_address(ch);
+            },
+            SetAddressRegToLargeCharInReg(reg) => {
+                let ch = try!(self.reg(reg));
+                self.address_reg = self.large_font_address(ch);
+            },
+
+            // Stays on this instruction until `check_awaited_key` moves past it, so
+            // debuggers and save states see the machine as still running the FX0A
+            WaitForKeyInReg(reg) => {
+                try!(self.reg(reg));
+                self.awaiting_key = Some(reg as usize);
+                self.pc -= 2;
+            },
+            SkipIfKeyInRegPressed { not_pressed, reg } => {
+                // There are only 16 keys, anything past them is never pressed
+                let key = try!(self.reg(reg)) as usize;
+                let mut should_jump = keys.get(key).cloned().unwrap_or(false);
+
+                if not_pressed {
+                    should_jump = !should_jump;
+                }
+
+                if should_jump {
+                    self.pc += 2;
+                }
+            },
+
+            // See http://en.wikipedia.org/wiki/Binary-coded_decimal
+            // n mod 10 => Gets the ones digit out of a number
+            RegToBCD(reg) => {
+                let number = try!(self.reg(reg));
+
+                let hundreds_digit = number / 100;
+                let tens_digit = (number / 10) % 10; // Dividing by ten slides the tens digit into the ones digit
+                let ones_digit = number % 10;
+
+                // Same policy as DrawSprite, writing past the end of memory is an error
+                try!(self.check_bounds(self.address_reg, 3));
+                self.note_code_write(self.address_reg, 3);
+
+                self.memory[(self.address_reg) as usize] = hundreds_digit;
+                self.memory[(self.address_reg + 1) as usize] = tens_digit;
+                self.memory[(self.address_reg + 2) as usize] = ones_digit;
+            },
+
+            DumpRegsToAddr(reg) => {
+                try!(self.reg(reg));
+                try!(self.check_bounds(self.address_reg, reg as usize + 1));
+                self.note_code_write(self.address_reg, reg as usize + 1);
+
+                for cur_reg in 0..(reg + 1) {
+                    self.memory[(self.address_reg + cur_reg as u16)  as usize] = self.regs[cur_reg as usize];  
+                }
+
+                if self.quirks.load_store_increments_i {
+                    self.address_reg += reg as u16 + 1;
+                }
+            },
+            LoadRegsFromAddr(reg) => {
+                try!(self.reg(reg));
+                try!(self.check_bounds(self.address_reg, reg as usize + 1));
+
+                for cur_reg in 0..(reg + 1) {
+                    self.regs[cur_reg as usize] = self.memory[(self.address_reg + cur_reg as u16) as usize];
+                }
+
+                if self.quirks.load_store_increments_i {
+                    self.address_reg += reg as u16 + 1;
+                }
+            },
+
+            // There are only 8 flags, so anything past V7 is ignored
+            DumpRegsToFlags(reg) => {
+                try!(self.reg(reg));
+                let last = cmp::min(reg, 7) as usize;
+
+                self.rpl[..last + 1].copy_from_slice(&self.regs[..last + 1]);
+            },
+            LoadRegsFromFlags(reg) => {
+                try!(self.reg(reg));
+                let last = cmp::min(reg, 7) as usize;
+
+                self.regs[..last + 1].copy_from_slice(&self.rpl[..last + 1]);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Timers run at 60Hz regardless of how many instructions are executed, so
+    // frontends are expected to call this 60 times a second separately from `cycle`
+    pub fn tick_timers(&mut self) {
+        self.timers.tick();
+    }
+
+    pub fn advance_timers(&mut self, micros: u64) {
+        self.timers.advance(micros);
+    }
+
+    pub fn screen_dirty(&self) -> bool {
+        self.dirty.iter().any(|row| *row)
+    }
+
+    // Which rows of the current resolution changed since the last call, so frontends
+    // that are slow to draw can skip the rest. Changing resolution marks every row
+    pub fn take_dirty(&mut self) -> [bool; SCREEN_HEIGHT] {
+        let dirty = self.dirty;
+        self.dirty = [false; SCREEN_HEIGHT];
+
+        dirty
+    }
+
+    // Events are worked out from the state, so something that starts and
+    // finishes between calls (a very short beep, say) isn't reported
+    pub fn take_events(&mut self) -> Vec<Chip8Event> {
+        use self::Chip8Event::*;
+
+        let mut events = Vec::new();
+
+        if self.screen_changed {
+            self.screen_changed = false;
+            events.push(ScreenChanged);
+        }
+
+        let sound_on = self.sound_playing();
+        if sound_on != self.sound_was_on {
+            self.sound_was_on = sound_on;
+            events.push(if sound_on { SoundStarted } else { SoundStopped });
+        }
+
+        let waiting = self.awaiting_key.is_some();
+        if waiting && !self.was_waiting_for_key {
+            events.push(WaitingForKey);
+        }
+        self.was_waiting_for_key = waiting;
+
+        events
+    }
+
+    // Records any of the `len` bytes at `start` that fall inside the loaded program.  By the
+    // time an opcode runs `pc` has moved past it, so the writer is the instruction before
+    fn note_code_write(&mut self, start: u16, len: usize) {
+        if !self.watch_code_writes {
+            return;
+        }
+
+        let code_start = self.program_start as usize;
+        for addr in start as usize..start as usize + len {
+            if addr >= code_start && addr < code_start + self.program_len {
+                *self.code_writes.entry((self.pc.wrapping_sub(2), addr as u16)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    // Every instruction that wrote into the program while `watch_code_writes` was on, by the address written.
+    // Sprite data and variables kept alongside the code show up here too, not just self-modifying code
+    pub fn code_write_report(&self) -> String {
+        let mut writes: Vec<(&(u16, u16), &u64)> = self.code_writes.iter().collect();
+        writes.sort_by_key(|&(&(pc, addr), _)| (addr, pc));
+
+        let mut report = String::new();
+        for (&(pc, addr), count) in writes {
+            report.push_str(&format!("0x{:03X}: wrote 0x{:03X} {} times, now 0x{:02X}\n", pc, addr, count, self.memory[addr as usize]));
+        }
+
+        report
+    }
+
+    // How many times each opcode has run while `profiling` was on, most frequent first
+    pub fn profile_report(&self) -> String {
+        let mut counts: Vec<(&&'static str, &u64)> = self.profile.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        let total: u64 = self.profile.values().sum();
+        let mut report = String::new();
+
+        for (name, count) in counts {
+            let percent = *count as f64 * 100.0 / total as f64;
+            report.push_str(&format!("{:>10} {:>6.2}% {}\n", count, percent, name));
+        }
+
+        report
+    }
+
+    // A 16 bytes per row hex and ASCII dump of memory, cut short at the end of memory
+    pub fn hex_dump(&self, start: u16, len: u16) -> String {
+        let start = cmp::min(start as usize, MEMORY_SIZE);
+        let end = cmp::min(start + len as usize, MEMORY_SIZE);
+
+        let mut dump = String::new();
+
+        for (index, row) in self.memory[start..end].chunks(16).enumerate() {
+            let hex: Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let ascii: String = row.iter()
+                .map(|&byte| if byte >= 0x20 && byte < 0x7F { byte as char } else { '.' })
+                .collect();
+
+            // Pad short rows so the ASCII column still lines up
+            dump.push_str(&format!("0x{:03X}: {:<47} |{}|\n", start + index * 16, hex.join(" "), ascii));
+        }
+
+        dump
+    }
+}
+
+impl fmt::Debug for Chip8 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(fmt, "Program Counter: 0x{:X} ({})", self.pc, self.pc));
+        try!(writeln!(fmt, "Address Register: 0x{:X} ({})", self.address_reg, self.address_reg));
+        try!(writeln!(fmt, "Stack: {:?}", self.stack));
+        try!(writeln!(fmt, "Delay Timer: {}", self.timers.delay));
+        try!(writeln!(fmt, "Sound Timer: {}", self.timers.sound));
+        try!(writeln!(fmt, "Instructions Executed: {}", self.cycles));
+        try!(writeln!(fmt, "Last Sprite Collisions: {}", self.last_collisions));
+
+        write!(fmt, "Register Contents: {:?}", self.regs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use testutil::{assert_screens_eq, lores, screen_from_rows};
+
+    // A machine from a fixed seed with `program` loaded, one opcode per word
+    fn machine(program: &[u16]) -> Chip8 {
+        machine_with(Quirks::default(), program)
+    }
+
+    fn machine_with(quirks: Quirks, program: &[u16]) -> Chip8 {
+        let rom: Vec<u8> = program.iter().flat_map(|word| vec![(word >> 8) as u8, *word as u8]).collect();
+        Chip8Builder::new().quirks(quirks).seed(0).rom(&rom).build().unwrap()
+    }
+
+    // Runs one instruction for each word of the program that was loaded
+    fn run(chip8: &mut Chip8, instructions: usize) {
+        for _ in 0..instructions {
+            chip8.step_instruction(None).unwrap();
+        }
+    }
+
+    #[test]
+    fn copy_reg() {
+        let mut chip8 = machine(&[0x6142, 0x8210]); // LD V1, 0x42; LD V2, V1
+        run(&mut chip8, 2);
+
+        assert_eq!(chip8.regs[2], 0x42);
+    }
+
+    #[test]
+    fn logic_ops() {
+        // V1 = 0x3C, V2 = 0x0F, then OR, AND and XOR into V3, V4 and V5
+        let mut chip8 = machine(&[0x613C, 0x620F, 0x8310, 0x8321, 0x8410, 0x8422, 0x8510, 0x8523]);
+        run(&mut chip8, 8);
+
+        assert_eq!(chip8.regs[3], 0x3F);
+        assert_eq!(chip8.regs[4], 0x0C);
+        assert_eq!(chip8.regs[5], 0x33);
+    }
+
+    #[test]
+    fn logic_ops_reset_vf_with_quirk() {
+        let program = [0x6F05, 0x8121]; // LD VF, 5; OR V1, V2
+        let mut quirks = Quirks::default();
+
+        let mut chip8 = machine_with(quirks, &program);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.regs[0xF], 5);
+
+        quirks.vf_reset_on_logic = true;
+        let mut chip8 = machine_with(quirks, &program);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.regs[0xF], 0);
+    }
+
+    #[test]
+    fn add_regs_sets_carry() {
+        // 0xF0 + 0x20 carries, 0x10 + 0x20 doesn't
+        let mut chip8 = machine(&[0x61F0, 0x6220, 0x8124, 0x8124]);
+
+        run(&mut chip8, 3);
+        assert_eq!((chip8.regs[1], chip8.regs[0xF]), (0x10, 1));
+
+        run(&mut chip8, 1);
+        assert_eq!((chip8.regs[1], chip8.regs[0xF]), (0x30, 0));
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_destination() {
+        let mut chip8 = machine(&[0x6FFF, 0x6102, 0x8F14]); // LD VF, 0xFF; LD V1, 2; ADD VF, V1
+        run(&mut chip8, 3);
+
+        assert_eq!(chip8.regs[0xF], 1);
+    }
+
+    #[test]
+    fn shifts_use_vx() {
+        // V1 = 0x81 and V2 = 0x02, VY is ignored without the quirk
+        let mut chip8 = machine(&[0x6181, 0x6202, 0x8126]);
+        run(&mut chip8, 3);
+        assert_eq!((chip8.regs[1], chip8.regs[0xF]), (0x40, 1));
+
+        let mut chip8 = machine(&[0x6181, 0x6202, 0x812E]);
+        run(&mut chip8, 3);
+        assert_eq!((chip8.regs[1], chip8.regs[0xF]), (0x02, 1));
+    }
+
+    #[test]
+    fn shifts_use_vy_with_quirk() {
+        let mut quirks = Quirks::default();
+        quirks.shift_uses_vy = true;
+
+        let mut chip8 = machine_with(quirks, &[0x6181, 0x6202, 0x8126]);
+        run(&mut chip8, 3);
+        assert_eq!((chip8.regs[1], chip8.regs[0xF]), (0x01, 0));
+
+        let mut chip8 = machine_with(quirks, &[0x6181, 0x6202, 0x812E]);
+        run(&mut chip8, 3);
+        assert_eq!((chip8.regs[1], chip8.regs[0xF]), (0x04, 0));
+    }
+
+    #[test]
+    fn clear_screen() {
+        let mut chip8 = machine(&[0x00E0]);
+        chip8.screen[3][4] = true;
+        run(&mut chip8, 1);
+
+        assert_screens_eq(&lores(&chip8.screen), &[[false; 64]; 32]);
+    }
+
+    #[test]
+    fn call_and_return() {
+        // CALL 0x206; JP 0x202 (never reached); JP 0x204; RET
+        let mut chip8 = machine(&[0x2206, 0x1202, 0x1204, 0x00EE]);
+
+        run(&mut chip8, 1);
+        assert_eq!((chip8.pc, chip8.stack.clone()), (0x206, vec![0x202]));
+
+        run(&mut chip8, 1);
+        assert_eq!((chip8.pc, chip8.stack.len()), (0x202, 0));
+    }
+
+    #[test]
+    fn return_without_call_fails() {
+        let mut chip8 = machine(&[0x00EE]);
+
+        match chip8.step_instruction(None) {
+            Err(RuntimeError::EmptyCallStack) => {},
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn jump() {
+        let mut chip8 = machine(&[0x1ABC]);
+        run(&mut chip8, 1);
+
+        assert_eq!(chip8.pc, 0xABC);
+    }
+
+    #[test]
+    fn jump_plus_register() {
+        // LD V0, 4; LD V3, 8; JP V0, 0x300
+        let program = [0x6004, 0x6308, 0xB300];
+
+        let mut chip8 = machine(&program);
+        run(&mut chip8, 3);
+        assert_eq!(chip8.pc, 0x304);
+
+        // BXNN adds V3 instead, from the high nibble of 0x300
+        let mut quirks = Quirks::default();
+        quirks.jump_uses_vx = true;
+
+        let mut chip8 = machine_with(quirks, &program);
+        run(&mut chip8, 3);
+        assert_eq!(chip8.pc, 0x308);
+    }
+
+    #[test]
+    fn skip_on_const() {
+        let mut chip8 = machine(&[0x6107, 0x3107]); // LD V1, 7; SE V1, 7
+        run(&mut chip8, 2);
+        assert_eq!(chip8.pc, 0x206);
+
+        let mut chip8 = machine(&[0x6107, 0x4107]); // SNE V1, 7
+        run(&mut chip8, 2);
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn skip_on_regs() {
+        let mut chip8 = machine(&[0x6107, 0x6207, 0x5120]); // SE V1, V2
+        run(&mut chip8, 3);
+        assert_eq!(chip8.pc, 0x208);
+
+        let mut chip8 = machine(&[0x6107, 0x6207, 0x9120]); // SNE V1, V2
+        run(&mut chip8, 3);
+        assert_eq!(chip8.pc, 0x206);
+    }
+
+    #[test]
+    fn add_const_wraps_without_touching_vf() {
+        let mut chip8 = machine(&[0x61FF, 0x7102]);
+        run(&mut chip8, 2);
+
+        assert_eq!((chip8.regs[1], chip8.regs[0xF]), (0x01, 0));
+    }
+
+    #[test]
+    fn random_is_masked() {
+        let mut chip8 = machine(&[0xC10F, 0xC200]);
+        run(&mut chip8, 2);
+
+        assert!(chip8.regs[1] <= 0x0F);
+        assert_eq!(chip8.regs[2], 0);
+    }
+
+    #[test]
+    fn draw_font_digit() {
+        // LD V0, 2; LD F, V0; LD V1, 1; DRW V1, V1, 5
+        let mut chip8 = machine(&[0x6002, 0xF029, 0x6101, 0xD115]);
+        run(&mut chip8, 4);
+
+        assert_screens_eq(&lores(&chip8.screen), &screen_from_rows(&[
+            "",
+            "011110",
+            "000010",
+            "011110",
+            "010000",
+            "011110",
+        ]));
+        assert_eq!(chip8.regs[0xF], 0);
+    }
+
+    #[test]
+    fn skip_on_key() {
+        let program = [0x6105, 0xE19E]; // LD V1, 5; SKP V1
+        let mut keys = [false; 16];
+        keys[5] = true;
+
+        let mut chip8 = machine(&program);
+        chip8.step_instruction(Some(keys)).unwrap();
+        chip8.step_instruction(Some(keys)).unwrap();
+        assert_eq!(chip8.pc, 0x206);
+
+        let mut chip8 = machine(&[0x6105, 0xE1A1]); // SKNP V1
+        chip8.step_instruction(Some(keys)).unwrap();
+        chip8.step_instruction(Some(keys)).unwrap();
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn timers() {
+        // LD V1, 10; LD DT, V1; LD ST, V1; LD V2, DT
+        let mut chip8 = machine(&[0x610A, 0xF115, 0xF118, 0xF207]);
+        run(&mut chip8, 3);
+        chip8.tick_timers();
+        run(&mut chip8, 1);
+
+        assert_eq!(chip8.regs[2], 9);
+        assert_eq!(chip8.sound(), 9);
+    }
+
+    #[test]
+    fn add_to_address_reg() {
+        let mut chip8 = machine(&[0xA300, 0x6110, 0xF11E]);
+        run(&mut chip8, 3);
+
+        assert_eq!(chip8.address_reg, 0x310);
+    }
+
+    #[test]
+    fn bcd() {
+        // LD V1, 234; LD I, 0x300; LD B, V1
+        let mut chip8 = machine(&[0x61EA, 0xA300, 0xF133]);
+        run(&mut chip8, 3);
+
+        assert_eq!(&chip8.memory[0x300..0x303], &[2, 3, 4]);
+    }
+
+    #[test]
+    fn store_and_load_regs() {
+        // LD V0, 1; LD V1, 2; LD I, 0x300; LD [I], V1; LD V0, 0; LD V1, 0; LD V1, [I]
+        let program = [0x6001, 0x6102, 0xA300, 0xF155, 0x6000, 0x6100, 0xF165];
+
+        let mut chip8 = machine(&program);
+        run(&mut chip8, 7);
+        assert_eq!(&chip8.memory[0x300..0x302], &[1, 2]);
+        assert_eq!((chip8.regs[0], chip8.regs[1], chip8.address_reg), (1, 2, 0x300));
+
+        // I ends up past both the store and the load
+        let mut quirks = Quirks::default();
+        quirks.load_store_increments_i = true;
+
+        let mut chip8 = machine_with(quirks, &program);
+        run(&mut chip8, 7);
+        assert_eq!(chip8.address_reg, 0x304);
+    }
+
+    // A machine with VF left at 0xAA, so tests can tell a cleared flag from one that was never written
+    fn sprite_machine(program: &[u16], sprite: &[u8]) -> Chip8 {
+        let mut chip8 = machine(program);
+        chip8.memory[0x300..0x300 + sprite.len()].copy_from_slice(sprite);
+        chip8.address_reg = 0x300;
+        chip8.regs[0xF] = 0xAA;
+
+        chip8
+    }
+
+    #[test]
+    fn draw_without_overlap_clears_vf() {
+        let mut chip8 = sprite_machine(&[0xD003, 0xD103], &[0x80, 0x40, 0x20]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.regs[0xF], 0);
+
+        // Sharing rows with the first sprite isn't enough, the pixels themselves have to overlap
+        chip8.regs[1] = 4;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.regs[0xF], 0);
+    }
+
+    #[test]
+    fn collision_in_any_row_sets_vf() {
+        // The second sprite only overlaps the first in its last row
+        let mut chip8 = sprite_machine(&[0xD003, 0xD003], &[0x80, 0x40, 0x20]);
+        run(&mut chip8, 1);
+
+        chip8.memory[0x300..0x303].copy_from_slice(&[0x40, 0x80, 0x20]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.regs[0xF], 1);
+
+        // A later row that doesn't collide doesn't clear it again
+        assert_screens_eq(&lores(&chip8.screen), &screen_from_rows(&["11", "11"]));
+    }
+
+    #[test]
+    fn collision_after_wrapping_sets_vf() {
+        // At (63, 31) a 2x3 sprite wraps around both edges, and its last two rows land on the first sprite
+        let mut chip8 = sprite_machine(&[0xD003, 0xD123], &[0xC0, 0xC0, 0xC0]);
+        run(&mut chip8, 1);
+
+        chip8.regs[1] = 63;
+        chip8.regs[2] = 31;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.regs[0xF], 1);
+        assert!(!chip8.screen[0][0] && !chip8.screen[1][0] && chip8.screen[2][0]);
+        assert!(chip8.screen[31][63] && chip8.screen[31][0] && chip8.screen[0][63]);
+    }
+
+    #[test]
+    fn clipped_pixels_never_collide() {
+        let mut quirks = Quirks::default();
+        quirks.clip_sprites = true;
+
+        let mut chip8 = machine_with(quirks, &[0xD003, 0xD123]);
+        chip8.memory[0x300..0x303].copy_from_slice(&[0xC0, 0xC0, 0xC0]);
+        chip8.address_reg = 0x300;
+        run(&mut chip8, 1);
+
+        chip8.regs[1] = 63;
+        chip8.regs[2] = 31;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.regs[0xF], 0);
+        assert!(chip8.screen[31][63]);
+        assert!(chip8.screen[0][0]);
+    }
+
+    #[test]
+    fn vf_as_coordinate_is_read_before_it_is_cleared() {
+        // DRW VF, VF with VF = 8 draws at (8, 8), then collides there
+        let mut chip8 = sprite_machine(&[0xDFF1, 0x6F08, 0xDFF1], &[0x80]);
+        chip8.regs[0xF] = 8;
+        run(&mut chip8, 3);
+
+        assert_eq!(chip8.regs[0xF], 1);
+        assert!(!chip8.screen[8][8]);
+    }
+
+    // Sets V1 to `first` and V2 to `second`, then runs `opcode` and returns (V1, VF)
+    fn subtract(first: u8, second: u8, opcode: u16) -> (u8, u8) {
+        let mut chip8 = machine(&[0x6100 | first as u16, 0x6200 | second as u16, opcode]);
+        run(&mut chip8, 3);
+
+        (chip8.regs[1], chip8.regs[0xF])
+    }
+
+    #[test]
+    fn sub_sets_vf_when_nothing_is_borrowed() {
+        assert_eq!(subtract(0x0A, 0x05, 0x8125), (0x05, 1)); // SUB V1, V2
+        assert_eq!(subtract(0x05, 0x0A, 0x8125), (0xFB, 0));
+
+        // Equal values don't borrow either
+        assert_eq!(subtract(0x05, 0x05, 0x8125), (0x00, 1));
+    }
+
+    #[test]
+    fn subn_sets_vf_when_nothing_is_borrowed() {
+        assert_eq!(subtract(0x05, 0x0A, 0x8127), (0x05, 1)); // SUBN V1, V2
+        assert_eq!(subtract(0x0A, 0x05, 0x8127), (0xFB, 0));
+        assert_eq!(subtract(0x05, 0x05, 0x8127), (0x00, 1));
+    }
+
+    #[test]
+    fn sub_into_vf_keeps_the_flag() {
+        // LD VF, 0x0A; LD V1, 0x05; SUB VF, V1
+        let mut chip8 = machine(&[0x6F0A, 0x6105, 0x8F15]);
+        run(&mut chip8, 3);
+
+        assert_eq!(chip8.regs[0xF], 1);
+    }
+
+    #[test]
+    fn add_to_address_reg_wraps() {
+        for &sets_vf in &[false, true] {
+            let mut quirks = Quirks::default();
+            quirks.add_to_i_sets_vf = sets_vf;
+
+            // LD V1, 0x20; LD VF, 0xAA; ADD I, V1
+            let mut chip8 = machine_with(quirks, &[0x6120, 0x6FAA, 0xF11E]);
+            chip8.address_reg = 0xFFF0;
+            run(&mut chip8, 3);
+
+            // Without the quirk VF is left alone
+            let vf = if sets_vf { 1 } else { 0xAA };
+            assert_eq!((chip8.address_reg, chip8.regs[0xF]), (0x0010, vf), "add_to_i_sets_vf = {}", sets_vf);
+        }
+    }
+
+    #[test]
+    fn add_to_address_reg_sets_vf_with_quirk() {
+        let mut quirks = Quirks::default();
+        quirks.add_to_i_sets_vf = true;
+
+        let mut chip8 = machine_with(quirks, &[0x6101, 0xF11E, 0xF11E]);
+        chip8.address_reg = 0xFFE;
+        run(&mut chip8, 2);
+        assert_eq!((chip8.address_reg, chip8.regs[0xF]), (0xFFF, 0));
+
+        run(&mut chip8, 1);
+        assert_eq!((chip8.address_reg, chip8.regs[0xF]), (0x1000, 1));
+    }
+
+    // Runs one instruction that's expected to fail
+    fn run_err(chip8: &mut Chip8) -> RuntimeError {
+        match chip8.step_instruction(None) {
+            Ok(opcode) => panic!("{} ran without an error", opcode),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn draw_past_end_of_memory_fails() {
+        let mut chip8 = machine(&[0xD00F]); // DRW V0, V0, 15
+        chip8.address_reg = 0xFFF;
+
+        match run_err(&mut chip8) {
+            RuntimeError::AddressOutOfBounds(0xFFF) => {},
+            err => panic!("{}", err),
+        }
+        assert_screens_eq(&lores(&chip8.screen), &[[false; 64]; 32]);
+    }
+
+    #[test]
+    fn draw_up_to_end_of_memory() {
+        let mut chip8 = machine(&[0xD002]);
+        chip8.address_reg = 0xFFE;
+        chip8.memory[0xFFE] = 0x80;
+        chip8.memory[0xFFF] = 0x80;
+        run(&mut chip8, 1);
+
+        assert_screens_eq(&lores(&chip8.screen), &screen_from_rows(&["1", "1"]));
+    }
+
+    #[test]
+    fn scroll_down_and_sideways() {
+        // SCD 2; SCR; SCL
+        let mut chip8 = machine(&[0x00C2, 0x00FB, 0x00FC]);
+        chip8.screen[0][0] = true;
+        chip8.screen[1][1] = true;
+
+        run(&mut chip8, 1);
+        assert_screens_eq(&lores(&chip8.screen), &screen_from_rows(&["", "", "1", "01"]));
+
+        run(&mut chip8, 1);
+        assert_screens_eq(&lores(&chip8.screen), &screen_from_rows(&["", "", "00001", "000001"]));
+
+        run(&mut chip8, 1);
+        assert_screens_eq(&lores(&chip8.screen), &screen_from_rows(&["", "", "1", "01"]));
+    }
+
+    #[test]
+    fn scrolled_off_pixels_are_lost() {
+        let mut chip8 = machine(&[0x00FB, 0x00FC, 0x00C1]);
+        chip8.screen[0][62] = true;
+        chip8.screen[31][0] = true;
+
+        run(&mut chip8, 3);
+        assert_screens_eq(&lores(&chip8.screen), &[[false; 64]; 32]);
+    }
+
+    #[test]
+    fn scroll_in_high_resolution() {
+        // HIGH; SCD 1, which moves a pixel at 128x64 instead of the 64x32 scale
+        let mut chip8 = machine(&[0x00FF, 0x00C1]);
+        run(&mut chip8, 1);
+        chip8.screen[62][127] = true;
+        run(&mut chip8, 1);
+
+        assert!(chip8.screen[63][127]);
+        assert_eq!(chip8.screen.iter().flat_map(|row| row.iter()).filter(|pixel| **pixel).count(), 1);
+    }
+
+    #[test]
+    fn program_filling_memory_loads() {
+        let rom = vec![0xAB; MEMORY_SIZE - PROGRAM_START as usize];
+        let mut chip8 = Chip8::new();
+
+        chip8.load_program(&rom[..]).unwrap();
+        assert_eq!(chip8.memory[MEMORY_SIZE - 1], 0xAB);
+    }
+
+    #[test]
+    fn program_too_large_fails() {
+        let rom = vec![0xAB; MEMORY_SIZE - PROGRAM_START as usize + 1];
+        let mut chip8 = Chip8::new();
+
+        match chip8.load_program(&rom[..]) {
+            Err(LoadError::ProgramTooLarge(3585, 3584)) => {},
+            other => panic!("{:?}", other),
+        }
+        assert!(chip8.memory[PROGRAM_START as usize..].iter().all(|byte| *byte == 0));
+    }
+
+    // A full 8x2 sprite drawn at (60, 31), which hangs off both the right and bottom edges
+    fn draw_off_the_corner(clip_sprites: bool) -> Chip8 {
+        let mut quirks = Quirks::default();
+        quirks.clip_sprites = clip_sprites;
+
+        let mut chip8 = machine_with(quirks, &[0x613C, 0x621F, 0xD122]);
+        chip8.memory[0x300..0x302].copy_from_slice(&[0xFF, 0xFF]);
+        chip8.address_reg = 0x300;
+        run(&mut chip8, 3);
+
+        chip8
+    }
+
+    #[test]
+    fn sprites_wrap_by_default() {
+        let chip8 = draw_off_the_corner(false);
+
+        let mut expected = screen_from_rows(&["1111"]);
+        expected[0][60..].copy_from_slice(&[true; 4]);
+        expected[31][..4].copy_from_slice(&[true; 4]);
+        expected[31][60..].copy_from_slice(&[true; 4]);
+
+        assert_screens_eq(&lores(&chip8.screen), &expected);
+    }
+
+    #[test]
+    fn sprites_clip_with_quirk() {
+        let chip8 = draw_off_the_corner(true);
+
+        let mut expected = [[false; 64]; 32];
+        expected[31][60..].copy_from_slice(&[true; 4]);
+
+        assert_screens_eq(&lores(&chip8.screen), &expected);
+    }
+
+    #[test]
+    fn sprite_start_wraps_even_when_clipping() {
+        // X = 66 starts the sprite at column 2 under either quirk
+        let mut quirks = Quirks::default();
+        quirks.clip_sprites = true;
+
+        let mut chip8 = machine_with(quirks, &[0x6142, 0xD101]);
+        chip8.memory[0x300] = 0x80;
+        chip8.address_reg = 0x300;
+        run(&mut chip8, 2);
+
+        assert_screens_eq(&lores(&chip8.screen), &screen_from_rows(&["001"]));
+    }
+
+    #[test]
+    fn from_bytes_loads_the_rom() {
+        let chip8 = Chip8::from_bytes(&[0x12, 0x34, 0x56]).unwrap();
+
+        assert_eq!(&chip8.memory[0x200..0x204], &[0x12, 0x34, 0x56, 0x00]);
+        assert_eq!(chip8.pc, PROGRAM_START);
+        assert!(chip8.fonts_intact());
+    }
+
+    #[test]
+    fn from_bytes_rejects_oversized_roms() {
+        match Chip8::from_bytes(&[0; MEMORY_SIZE]) {
+            Err(LoadError::ProgramTooLarge(..)) => {},
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn calls_past_stack_limit_overflow() {
+        // CALL 0x200 forever
+        let mut chip8 = machine(&[0x2200]);
+        run(&mut chip8, 16);
+        assert_eq!(chip8.stack.len(), 16);
+
+        match run_err(&mut chip8) {
+            RuntimeError::StackOverflow => {},
+            err => panic!("{}", err),
+        }
+        assert_eq!(chip8.stack.len(), 16);
+    }
+
+    #[test]
+    fn stack_limit_is_configurable() {
+        let mut chip8 = Chip8Builder::new().stack_limit(2).rom(&[0x22, 0x00]).build().unwrap();
+        run(&mut chip8, 2);
+
+        match run_err(&mut chip8) {
+            RuntimeError::StackOverflow => {},
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn hex_dump_rows() {
+        let chip8 = Chip8::from_bytes(b"Hi!\x00\x01").unwrap();
+
+        assert_eq!(chip8.hex_dump(0x200, 5), format!("0x200: {:<47} |Hi!..|\n", "48 69 21 00 01"));
+        assert_eq!(chip8.hex_dump(0x200, 20).lines().count(), 2);
+    }
+
+    #[test]
+    fn hex_dump_stops_at_end_of_memory() {
+        let chip8 = Chip8::new();
+
+        assert_eq!(chip8.hex_dump(0xFF8, 0x100).lines().count(), 1);
+        assert!(chip8.hex_dump(0xFF8, 0x100).starts_with("0xFF8: 00 00 00 00 00 00 00 00 "));
+        assert_eq!(chip8.hex_dump(0x1000, 16), "");
+    }
+
+    #[test]
+    fn nothing_runs_while_waiting_for_key() {
+        // LD V1, K; LD V2, 1
+        let mut chip8 = machine(&[0xF10A, 0x6201]);
+        chip8.timers.delay = 10;
+
+        for _ in 0..5 {
+            chip8.cycle(Some([false; 16])).unwrap();
+            chip8.tick_timers();
+        }
+
+        assert_eq!((chip8.pc, chip8.regs[2], chip8.cycles), (0x200, 0, 1));
+        assert_eq!(chip8.awaiting_key, Some(1));
+        assert_eq!(chip8.timers.delay, 5);
+        assert!(chip8.take_events().contains(&Chip8Event::WaitingForKey));
+    }
+
+    fn keys_with(key: usize) -> [bool; 16] {
+        let mut keys = [false; 16];
+        keys[key] = true;
+
+        keys
+    }
+
+    #[test]
+    fn wait_for_key_stores_it_once_released() {
+        let mut chip8 = machine(&[0xF10A, 0x6201]); // LD V1, K; LD V2, 1
+        chip8.step_instruction(Some([false; 16])).unwrap();
+
+        // Held down isn't enough, however long for
+        for _ in 0..3 {
+            chip8.step_instruction(Some(keys_with(7))).unwrap();
+        }
+        assert_eq!((chip8.pc, chip8.regs[1], chip8.awaited_key_down), (0x200, 0, Some(7)));
+
+        // Releasing it stores it and moves on, and the next instruction runs in the same step
+        chip8.step_instruction(Some([false; 16])).unwrap();
+        assert_eq!((chip8.regs[1], chip8.regs[2], chip8.pc), (7, 1, 0x204));
+        assert_eq!(chip8.awaiting_key, None);
+    }
+
+    #[test]
+    fn key_held_before_the_wait_counts_once_released() {
+        let mut chip8 = machine(&[0xF30A, 0x1202]); // LD V3, K; JP 0x202
+        chip8.step_instruction(Some(keys_with(0xA))).unwrap();
+        chip8.step_instruction(Some(keys_with(0xA))).unwrap();
+        chip8.step_instruction(Some([false; 16])).unwrap();
+
+        assert_eq!(chip8.regs[3], 0xA);
+    }
+
+    // The first few CXFF results for a seed
+    fn random_bytes(seed: u64) -> Vec<u8> {
+        let mut chip8 = Chip8Builder::new().seed(seed).rom(&[0xC1, 0xFF, 0x12, 0x00]).build().unwrap();
+
+        (0..8).map(|_| {
+            run(&mut chip8, 2);
+            chip8.regs[1]
+        }).collect()
+    }
+
+    #[test]
+    fn same_seed_same_numbers() {
+        assert_eq!(random_bytes(1234), random_bytes(1234));
+        assert!(random_bytes(1234) != random_bytes(4321));
+    }
+
+    #[test]
+    fn reseeding_restarts_the_sequence() {
+        let mut chip8 = Chip8Builder::new().seed(99).rom(&[0xC1, 0xFF]).build().unwrap();
+        run(&mut chip8, 1);
+        let first = chip8.regs[1];
+
+        chip8.seed_rng(99);
+        chip8.pc = PROGRAM_START;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.regs[1], first);
+    }
+
+    #[test]
+    fn large_sprite_in_high_resolution() {
+        // HIGH; DRW V0, V0, 0 with a sprite whose rows are 0x8001, an outline of the left and right columns
+        let mut chip8 = machine(&[0x00FF, 0xD000, 0xD000]);
+        for row in 0..16 {
+            chip8.memory[0x300 + row * 2] = 0x80;
+            chip8.memory[0x301 + row * 2] = 0x01;
+        }
+        chip8.address_reg = 0x300;
+        run(&mut chip8, 2);
+
+        for y in 0..16 {
+            assert!(chip8.screen[y][0] && chip8.screen[y][15], "row {}", y);
+            assert!(!chip8.screen[y][1..15].iter().any(|pixel| *pixel), "row {}", y);
+        }
+        assert!(!chip8.screen[16][0]);
+        assert_eq!(chip8.last_collisions, 0);
+
+        // All 32 pixels collide the second time around
+        run(&mut chip8, 1);
+        assert_eq!((chip8.regs[0xF], chip8.last_collisions), (1, 32));
+    }
+
+    #[test]
+    fn large_sprite_opcode_draws_nothing_in_low_resolution() {
+        let mut chip8 = sprite_machine(&[0xD000], &[0xFF; 32]);
+        run(&mut chip8, 1);
+
+        assert_screens_eq(&lores(&chip8.screen), &[[false; 64]; 32]);
+        assert_eq!(chip8.regs[0xF], 0);
+    }
+
+    #[test]
+    fn pc_at_last_byte_of_memory_fails() {
+        let mut chip8 = machine(&[0x1FFF]);
+        run(&mut chip8, 1);
+
+        match run_err(&mut chip8) {
+            RuntimeError::AddressOutOfBounds(0xFFF) => {},
+            err => panic!("{}", err),
+        }
+        assert_eq!(chip8.pc, 0xFFF);
+    }
+
+    #[test]
+    fn last_word_of_memory_still_runs() {
+        let mut chip8 = machine(&[0x1FFE]);
+        chip8.memory[0xFFE] = 0x1F; // JP 0xFFE
+        chip8.memory[0xFFF] = 0xFE;
+        run(&mut chip8, 3);
+
+        assert_eq!(chip8.pc, 0xFFE);
+    }
+
+    #[test]
+    fn bcd_past_end_of_memory_fails() {
+        let mut chip8 = machine(&[0x61FF, 0xF133]); // LD V1, 255; LD B, V1
+        chip8.address_reg = 0xFFE;
+        run(&mut chip8, 1);
+
+        match run_err(&mut chip8) {
+            RuntimeError::AddressOutOfBounds(0xFFE) => {},
+            err => panic!("{}", err),
+        }
+        assert_eq!(&chip8.memory[0xFFE..], &[0, 0]);
+    }
+
+    #[test]
+    fn bcd_in_last_three_bytes() {
+        let mut chip8 = machine(&[0x61FF, 0xF133]);
+        chip8.address_reg = 0xFFD;
+        run(&mut chip8, 2);
+
+        assert_eq!(&chip8.memory[0xFFD..], &[2, 5, 5]);
+    }
+
+    #[test]
+    fn store_and_load_past_end_of_memory_fail() {
+        // LD [I], V3 and LD V3, [I] with I three bytes from the end
+        for opcode in &[0xF355, 0xF365] {
+            let mut chip8 = machine(&[*opcode]);
+            chip8.address_reg = 0xFFD;
+            chip8.regs[..4].copy_from_slice(&[1, 2, 3, 4]);
+
+            match run_err(&mut chip8) {
+                RuntimeError::AddressOutOfBounds(0xFFD) => {},
+                err => panic!("{}", err),
+            }
+            assert_eq!(&chip8.memory[0xFFD..], &[0, 0, 0]);
+            assert_eq!(&chip8.regs[..4], &[1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn store_up_to_end_of_memory() {
+        let mut chip8 = machine(&[0xF255]);
+        chip8.address_reg = 0xFFD;
+        chip8.regs[..3].copy_from_slice(&[1, 2, 3]);
+        run(&mut chip8, 1);
+
+        assert_eq!(&chip8.memory[0xFFD..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn rpl_flags_round_trip() {
+        // Fill V0 - V7, LD R, V7; clear them; LD V7, R
+        let mut chip8 = machine(&[0xF775, 0xF785]);
+        chip8.regs[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.rpl, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        chip8.regs = [0; REGISTER_COUNT];
+        run(&mut chip8, 1);
+        assert_eq!(&chip8.regs[..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn rpl_flags_stop_at_v7() {
+        // LD R, VF; then LD VF, R, neither of which touches anything past V7
+        let mut chip8 = machine(&[0xFF75, 0xFF85]);
+        chip8.regs = [9; REGISTER_COUNT];
+        run(&mut chip8, 1);
+        assert_eq!(chip8.rpl, [9; 8]);
+
+        chip8.rpl = [1; 8];
+        run(&mut chip8, 1);
+        assert_eq!(&chip8.regs[..8], &[1; 8]);
+        assert_eq!(&chip8.regs[8..], &[9; 8]);
+    }
+
+    #[test]
+    fn rpl_flags_survive_reset() {
+        let mut chip8 = machine(&[0xF075]);
+        chip8.regs[0] = 42;
+        run(&mut chip8, 1);
+        chip8.reset();
+
+        assert_eq!(chip8.rpl[0], 42);
+    }
+
+    #[test]
+    fn exit_halts_on_the_same_instruction() {
+        let mut chip8 = machine(&[0x6101, 0x00FD]);
+        run(&mut chip8, 1);
+
+        for _ in 0..2 {
+            match run_err(&mut chip8) {
+                RuntimeError::Halted => {},
+                err => panic!("{}", err),
+            }
+            assert_eq!(chip8.pc, 0x202);
+        }
+
+        match chip8.cycle(None) {
+            Err(RuntimeError::Halted) => {},
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn timers_advance_by_time() {
+        let mut timers = Timers::new();
+        timers.delay = 10;
+        timers.sound = 1;
+
+        // Less than a 60th of a second doesn't tick, but it carries over
+        timers.advance(10_000);
+        assert_eq!(timers.delay, 10);
+        timers.advance(10_000);
+        assert_eq!((timers.delay, timers.sound), (9, 0));
+
+        // A whole second is 60 ticks whatever size the pieces are
+        let mut timers = Timers::new();
+        timers.delay = 100;
+        for _ in 0..1000 {
+            timers.advance(1000);
+        }
+        assert_eq!(timers.delay, 40);
+    }
+
+    #[test]
+    fn step_ticks_timers_every_speed_plus_one_steps() {
+        let mut chip8 = Chip8Builder::new().speed(3).rom(&[0x12, 0x00]).build().unwrap();
+        chip8.timers.delay = 10;
+
+        for _ in 0..3 {
+            chip8.step(None).unwrap();
+        }
+        assert_eq!(chip8.timers.delay, 10);
+
+        chip8.step(None).unwrap();
+        assert_eq!(chip8.timers.delay, 9);
+
+        // Instructions run one at a time don't tick at all
+        chip8.step_instruction(None).unwrap();
+        assert_eq!(chip8.timers.delay, 9);
+    }
+
+    #[test]
+    fn clone_copies_the_machine() {
+        let mut chip8 = machine(&[0x6142, 0xA300, 0xD005, 0x2200]);
+        run(&mut chip8, 4);
+        chip8.take_dirty();
+
+        let mut copy = chip8.clone();
+        assert_eq!(&copy.memory[..], &chip8.memory[..]);
+        assert_eq!((copy.regs, copy.address_reg, copy.pc), (chip8.regs, chip8.address_reg, chip8.pc));
+        assert_eq!(copy.stack, chip8.stack);
+        assert_screens_eq(&lores(&copy.screen), &lores(&chip8.screen));
+
+        // The copy stands on its own, and gets drawn in full
+        copy.memory[0x300] = 0xFF;
+        copy.screen[0][0] = !copy.screen[0][0];
+        assert_eq!(chip8.memory[0x300], 0);
+        assert!(copy.screen[0][0] != chip8.screen[0][0]);
+        assert_eq!(copy.take_dirty(), [true; SCREEN_HEIGHT]);
+        assert!(!chip8.screen_dirty());
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame_after_a_sprite() {
+        // Two sprites in a row, then a marker in V2
+        let program = [0xD001, 0xD001, 0x6201, 0x1206];
+        let mut quirks = Quirks::default();
+        quirks.display_wait = true;
+
+        let mut chip8 = machine_with(quirks, &program);
+        chip8.set_speed(10);
+        chip8.cycle(None).unwrap();
+        assert_eq!((chip8.pc, chip8.cycles), (0x202, 1));
+
+        chip8.cycle(None).unwrap();
+        assert_eq!((chip8.pc, chip8.cycles), (0x204, 2));
+
+        // Everything else keeps running out the rest of the frame
+        chip8.cycle(None).unwrap();
+        assert_eq!(chip8.regs[2], 1);
+        assert_eq!(chip8.cycles, 2 + 11);
+    }
+
+    #[test]
+    fn display_wait_ticks_step_early() {
+        let mut quirks = Quirks::default();
+        quirks.display_wait = true;
+
+        let mut chip8 = machine_with(quirks, &[0xD001]);
+        chip8.set_speed(10);
+        chip8.timers.delay = 5;
+        chip8.step(None).unwrap();
+
+        assert_eq!(chip8.timers.delay, 4);
+    }
+
+    #[test]
+    fn sprites_dont_wait_without_quirk() {
+        let mut chip8 = machine(&[0xD001, 0xD001, 0x1204]);
+        chip8.set_speed(10);
+        chip8.cycle(None).unwrap();
+
+        assert_eq!(chip8.cycles, 11);
+    }
+
+    #[test]
+    fn builder_applies_everything() {
+        let chip8 = Chip8Builder::new()
+            .platform(Platform::CosmacVip)
+            .speed(20)
+            .stack_limit(4)
+            .program_start(0x600)
+            .rom(&[0x12, 0x34])
+            .build()
+            .unwrap();
+
+        assert_eq!(chip8.quirks, Platform::CosmacVip.quirks());
+        assert_eq!((chip8.speed, chip8.stack_limit), (20, 4));
+        assert_eq!((chip8.pc, &chip8.memory[0x600..0x602]), (0x600, &[0x12, 0x34][..]));
+        assert_eq!(chip8.memory[0x200], 0);
+    }
+
+    #[test]
+    fn builder_defaults_match_new() {
+        let built = Chip8Builder::new().build().unwrap();
+        let new = Chip8::new();
+
+        assert_eq!(&built.memory[..], &new.memory[..]);
+        assert_eq!((built.pc, built.speed, built.stack_limit, built.quirks), (new.pc, new.speed, new.stack_limit, new.quirks));
+    }
+
+    #[test]
+    fn later_quirks_replace_the_platform() {
+        let chip8 = Chip8Builder::new().platform(Platform::SuperChip).quirks(Quirks::default()).build().unwrap();
+        assert_eq!(chip8.quirks, Quirks::default());
+    }
+
+    #[test]
+    fn builder_reports_load_errors() {
+        match Chip8Builder::new().program_start(0x10).build() {
+            Err(LoadError::BadProgramStart(0x10)) => {},
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        match Chip8Builder::new().program_start(0xF00).rom(&[0; 0x101]).build() {
+            Err(LoadError::ProgramTooLarge(0x101, 0x100)) => {},
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn wait_for_key_stays_on_its_instruction() {
+        // LD V1, 5; LD V1, K; LD V1, 9
+        let mut chip8 = machine(&[0x6105, 0xF10A, 0x6109]);
+
+        for _ in 0..10 {
+            chip8.cycle(Some([false; 16])).unwrap();
+        }
+
+        assert_eq!((chip8.pc, chip8.regs[1]), (0x202, 5));
+        assert_eq!(chip8.peek_opcode().unwrap(), Opcode::WaitForKeyInReg(1));
+        assert_eq!(chip8.step_instruction(None).unwrap(), Opcode::WaitForKeyInReg(1));
+    }
+
+    #[test]
+    fn screen_bytes_layout() {
+        let mut chip8 = Chip8::new();
+        chip8.screen[0][0] = true;
+        chip8.screen[0][9] = true;
+        chip8.screen[31][63] = true;
+
+        let bytes = chip8.screen_to_bytes();
+        assert_eq!(bytes.len(), 256);
+        assert_eq!(&bytes[..2], &[0x80, 0x40]);
+        assert_eq!(bytes[255], 0x01);
+        assert_eq!(bytes.iter().filter(|byte| **byte != 0).count(), 3);
+    }
+
+    #[test]
+    fn screen_bytes_round_trip() {
+        // The font digits drawn down a diagonal of the high resolution screen
+        let mut chip8 = Chip8::from_bytes(&[0x00, 0xFF]).unwrap();
+        run(&mut chip8, 1);
+        for digit in 0..16 {
+            chip8.regs[0] = digit;
+            chip8.regs[1] = digit * 8;
+            chip8.execute_opcode(Opcode::SetAddressRegToCharInReg(0), [false; 16]).unwrap();
+            chip8.execute_opcode(Opcode::DrawSprite { regs: (1, 1), rows: 5 }, [false; 16]).unwrap();
+        }
+        let bytes = chip8.screen_to_bytes();
+        assert_eq!(bytes.len(), 1024);
+
+        let mut copy = Chip8::from_bytes(&[0x00, 0xFF]).unwrap();
+        run(&mut copy, 1);
+        copy.take_dirty();
+        assert!(copy.screen_from_bytes(&bytes));
+        assert_eq!(&copy.screen[..], &chip8.screen[..]);
+        assert!(copy.screen_dirty());
+    }
+
+    #[test]
+    fn screen_bytes_of_the_wrong_size_are_rejected() {
+        let mut chip8 = Chip8::new();
+
+        assert!(!chip8.screen_from_bytes(&[0xFF; 255]));
+        assert!(!chip8.screen_from_bytes(&[0xFF; 1024]));
+        assert_screens_eq(&lores(&chip8.screen), &[[false; 64]; 32]);
+    }
+
+    #[test]
+    fn step_instruction_returns_what_ran() {
+        let mut chip8 = machine(&[0x6142, 0x2206, 0x0000, 0xA123]);
+
+        assert_eq!(chip8.step_instruction(None).unwrap(), Opcode::SetRegToConst { add: false, reg: 1, value: 0x42 });
+        assert_eq!(chip8.step_instruction(None).unwrap(), Opcode::Call(0x206));
+        assert_eq!(chip8.step_instruction(None).unwrap(), Opcode::SetAddressReg(0x123));
+        assert_eq!((chip8.cycles, chip8.address_reg), (3, 0x123));
+    }
+
+    #[test]
+    fn step_instruction_reports_undecodable_opcodes() {
+        let mut chip8 = machine(&[0x5121]);
+
+        match run_err(&mut chip8) {
+            RuntimeError::OpcodeErr(0x200, _) => {},
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn collisions_are_counted_per_pixel() {
+        // A 4 pixel row drawn three times, then a single pixel drawn over the end of it
+        let mut chip8 = sprite_machine(&[0xD001, 0xD001, 0xD001, 0x6103, 0xA301, 0xD101], &[0xF0, 0x80]);
+        run(&mut chip8, 1);
+        assert_eq!((chip8.regs[0xF], chip8.last_collisions), (0, 0));
+
+        run(&mut chip8, 1);
+        assert_eq!((chip8.regs[0xF], chip8.last_collisions), (1, 4));
+
+        run(&mut chip8, 4);
+        assert_eq!((chip8.regs[0xF], chip8.last_collisions), (1, 1));
+    }
+
+    #[test]
+    fn collision_count_resets_with_the_machine() {
+        let mut chip8 = sprite_machine(&[0xD001, 0xD001], &[0xFF]);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.last_collisions, 8);
+
+        chip8.reset();
+        assert_eq!(chip8.last_collisions, 0);
+    }
+
+    #[test]
+    fn restore_from_keeps_debugging_aids() {
+        let mut chip8 = machine(&[0x6101, 0x6202]);
+        chip8.add_breakpoint(0x300);
+        chip8.add_condition("V1 == 5".parse().unwrap());
+        chip8.profiling = true;
+        chip8.watch_code_writes = true;
+        run(&mut chip8, 2);
+
+        // A fresh machine stands in for one loaded from a save state
+        let mut state = Chip8::new();
+        state.regs[1] = 5;
+        chip8.restore_from(state);
+
+        assert_eq!(chip8.regs[1], 5);
+        assert!(chip8.breakpoints.contains(&0x300));
+        assert!(chip8.profiling && chip8.watch_code_writes);
+        assert_eq!(chip8.profile.get("SetRegToConst"), Some(&2));
+
+        // V1 is already 5 in the new state, so the condition doesn't fire as soon as it runs
+        assert_eq!(chip8.conditions.len(), 1);
+        assert!(chip8.conditions[0].1);
+    }
+
+    #[test]
+    fn breakpoint_stops_once_then_resumes() {
+        let mut chip8 = machine(&[0x6101, 0x6202, 0x6303]);
+        chip8.add_breakpoint(0x202);
+        assert!(chip8.has_breakpoint(0x202));
+        run(&mut chip8, 1);
+
+        match run_err(&mut chip8) {
+            RuntimeError::BreakpointHit(0x202) => {},
+            err => panic!("{}", err),
+        }
+        assert_eq!((chip8.pc, chip8.regs[2]), (0x202, 0));
+
+        run(&mut chip8, 2);
+        assert_eq!((chip8.regs[2], chip8.regs[3]), (2, 3));
+
+        chip8.remove_breakpoint(0x202);
+        assert!(!chip8.has_breakpoint(0x202));
+    }
+
+    // A trace writer that can still be read from after it's handed to the machine
+    #[derive(Clone)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn restore_from_keeps_tracing() {
+        let buffer = SharedBuffer(Default::default());
+        let mut chip8 = machine(&[0x6101, 0x6202, 0x6303]);
+        chip8.trace = Some(Box::new(buffer.clone()));
+
+        // Restoring an earlier copy, like F6 and rewinding do
+        let earlier = chip8.clone();
+        run(&mut chip8, 1);
+        chip8.restore_from(earlier);
+        run(&mut chip8, 2);
+
+        let lines = buffer.0.borrow().iter().filter(|byte| **byte == b'\n').count();
+        assert_eq!(lines, 3);
+        assert!(chip8.trace.is_some());
+    }
+
+    #[test]
+    fn conditions_parse() {
+        assert_eq!("VF == 1".parse(), Ok(Condition::Register(0xF, Comparison::Equal, 1)));
+        assert_eq!("I >= 0x300".parse(), Ok(Condition::AddressReg(Comparison::GreaterOrEqual, 0x300)));
+        assert!("VF == ".parse::<Condition>().is_err());
+        assert!("VG == 1".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn custom_font() {
+        let font: Vec<u8> = (0..80).collect();
+        let mut chip8 = machine(&[0x600A, 0xF029]); // LD V0, 0xA; LD F, V0
+        chip8.set_font(0x000, &font).unwrap();
+        run(&mut chip8, 2);
+
+        assert_eq!(chip8.address_reg, 50);
+        assert_eq!(&chip8.memory[50..55], &[50, 51, 52, 53, 54]);
+        assert!(chip8.fonts_intact());
+    }
+
+    #[test]
+    fn fonts_must_be_80_bytes() {
+        let mut chip8 = Chip8::new();
+        for &size in &[79, 160] {
+            match chip8.set_font(0x000, &vec![0; size]) {
+                Err(LoadError::BadFontSize(bad)) if bad == size => {},
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(chip8.font_address(0), FONT_START);
+    }
+
+    #[test]
+    fn fonts_stay_clear_of_the_large_font() {
+        let mut chip8 = Chip8::new();
+        for &start in &[0x060, 0x0A0, 0x100, 0x13F] {
+            match chip8.set_font(start, &FONTMAP) {
+                Err(LoadError::BadFontStart(bad)) if bad == start => {},
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(chip8.font_address(0), FONT_START);
+
+        chip8.set_font(0x140, &FONTMAP).unwrap();
+        assert_eq!(chip8.font_address(0), 0x140);
+    }
+
+    #[test]
+    fn fonts_end_before_the_program() {
+        let mut chip8 = Chip8::new();
+        match chip8.set_font(0x1C0, &FONTMAP) {
+            Err(LoadError::BadFontStart(0x1C0)) => {},
+            other => panic!("{:?}", other),
+        }
+
+        chip8.set_font(0x1B0, &FONTMAP).unwrap();
+        assert_eq!(chip8.font_address(0xF), 0x1B0 + 75);
+    }
+
+    #[test]
+    fn every_digit_has_a_glyph() {
+        for digit in 0..16u8 {
+            // LD V0, digit; LD F, V0
+            let mut chip8 = machine(&[0x6000 | digit as u16, 0xF029]);
+            run(&mut chip8, 2);
+
+            let addr = chip8.font_address(digit);
+            assert_eq!(addr, FONT_START + digit as u16 * 5);
+            assert_eq!(chip8.address_reg, addr);
+
+            let glyph = &FONTMAP[digit as usize * 5..digit as usize * 5 + 5];
+            assert_eq!(&chip8.memory[addr as usize..addr as usize + 5], glyph, "digit {:X}", digit);
+        }
+    }
+
+    #[test]
+    fn fonts_intact_notices_overwrites() {
+        let mut chip8 = Chip8::new();
+        assert!(chip8.fonts_intact());
+
+        chip8.memory[chip8.font_address(0xB) as usize + 2] ^= 0xFF;
+        assert!(!chip8.fonts_intact());
+        chip8.reset();
+        assert!(chip8.fonts_intact());
+
+        chip8.memory[chip8.large_font_address(9) as usize] ^= 0xFF;
+        assert!(!chip8.fonts_intact());
+
+        let mut restored = Chip8::new();
+        restored.restore_from(chip8.clone());
+        assert!(!restored.fonts_intact());
+    }
+
+    #[test]
+    fn run_headless_runs_instructions() {
+        // LD V1, 1; ADD V1, 1; JP 0x202
+        let chip8 = run_headless(&[0x61, 0x01, 0x71, 0x01, 0x12, 0x02], 5, [false; 16]).unwrap();
+        assert_eq!((chip8.regs[1], chip8.cycles), (3, 5));
+    }
+
+    #[test]
+    fn run_headless_reports_load_errors() {
+        match run_headless(&vec![0; MEMORY_SIZE], 1, [false; 16]) {
+            Err(RuntimeError::Load(LoadError::ProgramTooLarge(..))) => {},
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn run_headless_key_waits() {
+        // LD V1, K; LD V2, 1; JP 0x204
+        let rom = [0xF1, 0x0A, 0x62, 0x01, 0x12, 0x04];
+
+        // Nothing held, so the wait uses up every instruction
+        let chip8 = run_headless(&rom, 10, [false; 16]).unwrap();
+        assert_eq!((chip8.pc, chip8.awaiting_key, chip8.regs[2]), (0x200, Some(1), 0));
+
+        // A held key still gets through
+        let mut keys = [false; 16];
+        keys[7] = true;
+        let chip8 = run_headless(&rom, 10, keys).unwrap();
+        assert_eq!((chip8.awaiting_key, chip8.regs[1], chip8.regs[2]), (None, 7, 1));
+        assert_eq!(chip8.keys(), keys);
+    }
+
+    #[test]
+    fn code_writes_are_counted_per_address() {
+        // LD I, 0x208; LD V0, 0xAB; LD [I], V0; JP 0x204; then the byte that gets written
+        let mut chip8 = machine(&[0xA208, 0x60AB, 0xF055, 0x1204, 0x0000]);
+        chip8.watch_code_writes = true;
+        run(&mut chip8, 2 + 2 * 50);
+
+        assert_eq!(chip8.code_writes.len(), 1);
+        assert_eq!(chip8.code_write_report(), "0x204: wrote 0x208 50 times, now 0xAB\n");
+
+        // Copies taken for rewinding leave them out, restoring keeps the running machine's
+        let copy = chip8.clone();
+        assert!(copy.code_writes.is_empty());
+        chip8.restore_from(copy);
+        assert_eq!(chip8.code_writes.get(&(0x204, 0x208)), Some(&50));
+    }
+
+    #[test]
+    fn program_start_has_to_clear_the_fonts() {
+        let mut chip8 = Chip8::new();
+        for &start in &[0x000, 0x0A0, 0x13F, 0x1000, 0xFFFF] {
+            match chip8.set_program_start(start) {
+                Err(LoadError::BadProgramStart(bad)) if bad == start => {},
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!((chip8.program_start(), chip8.pc), (PROGRAM_START, PROGRAM_START));
+
+        chip8.set_program_start(0x140).unwrap();
+        assert_eq!((chip8.program_start(), chip8.pc), (0x140, 0x140));
+    }
+
+    #[test]
+    fn programs_run_from_0x600() {
+        // LD V1, 0x42; JP 0x600
+        let mut chip8 = Chip8Builder::new().program_start(0x600).rom(&[0x61, 0x42, 0x16, 0x00]).build().unwrap();
+        assert!(chip8.memory[PROGRAM_START as usize..0x600].iter().all(|byte| *byte == 0));
+
+        run(&mut chip8, 1);
+        assert_eq!((chip8.regs[1], chip8.pc), (0x42, 0x602));
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x600);
+        assert!(chip8.fonts_intact());
+
+        chip8.reset();
+        assert_eq!((chip8.pc, chip8.regs[1]), (0x600, 0));
+    }
+
+    // Everything `diff` is meant to cover
+    fn assert_same_state(actual: &Chip8, expected: &Chip8) {
+        assert_eq!(&actual.memory[..], &expected.memory[..]);
+        assert_eq!((actual.regs, actual.address_reg, actual.pc), (expected.regs, expected.address_reg, expected.pc));
+        assert_eq!((&actual.stack, actual.timers), (&expected.stack, expected.timers));
+        assert_eq!((actual.hires, actual.plane_mask, actual.rpl), (expected.hires, expected.plane_mask, expected.rpl));
+        assert_eq!((actual.awaiting_key, actual.awaited_key_down), (expected.awaiting_key, expected.awaited_key_down));
+        assert_screens_eq(&lores(&actual.screen), &lores(&expected.screen));
+        assert_screens_eq(&lores(&actual.second_plane), &lores(&expected.second_plane));
+    }
+
+    #[test]
+    fn delta_brings_flags_across() {
+        // LD V0, 0x12; LD V1, 0x34; LD R, V1; LD I, 0x300; DRW V0, V1, 5
+        let a = machine(&[0x6012, 0x6134, 0xF175, 0xA300, 0xD015]);
+        let mut b = a.clone();
+        run(&mut b, 5);
+
+        let delta = a.diff(&b);
+        assert_eq!(delta.rpl, Some([0x12, 0x34, 0, 0, 0, 0, 0, 0]));
+
+        let mut copy = a.clone();
+        copy.apply_delta(&delta);
+        assert_same_state(&copy, &b);
+        assert!(copy.diff(&b).is_empty());
+    }
+
+    #[test]
+    fn delta_brings_key_waits_across() {
+        // LD V1, K; LD V2, 1
+        let a = machine(&[0xF10A, 0x6201]);
+        let mut b = a.clone();
+        run(&mut b, 1);
+        b.step_instruction(Some(keys_with(6))).unwrap();
+        assert_eq!((b.awaiting_key, b.awaited_key_down), (Some(1), Some(6)));
+
+        let mut copy = a.clone();
+        copy.apply_delta(&a.diff(&b));
+        assert_same_state(&copy, &b);
+
+        // And back out of the wait again
+        let mut copy = b.clone();
+        copy.apply_delta(&b.diff(&a));
+        assert_same_state(&copy, &a);
+        assert!(copy.diff(&a).is_empty());
+    }
+
+    #[test]
+    fn register_accessors_check_the_register() {
+        let mut chip8 = Chip8::new();
+        chip8.set_register(0xF, 0x12).unwrap();
+        assert_eq!(chip8.register(0xF).unwrap(), 0x12);
+
+        match (chip8.register(0x10), chip8.set_register(0x10, 1)) {
+            (Err(RuntimeError::InvalidRegister(0x10)), Err(RuntimeError::InvalidRegister(0x10))) => {},
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(&chip8.regs[..0xF], &[0; 15]);
+    }
+
+    #[test]
+    fn delta_brings_memory_timers_and_the_screen_across() {
+        // LD V0, 0x30; LD DT, V0; LD ST, V0; LD I, 0x300; LD B, V0; LD V1, 0; LD F, V1; DRW V1, V1, 5
+        let a = machine(&[0x6030, 0xF015, 0xF018, 0xA300, 0xF033, 0x6100, 0xF129, 0xD115]);
+        let mut b = a.clone();
+        run(&mut b, 8);
+
+        let delta = a.diff(&b);
+        assert!(delta.memory.contains(&(0x301, 4)) && delta.memory.contains(&(0x302, 8)));
+        assert_eq!(delta.timers.map(|timers| (timers.delay, timers.sound)), Some((0x30, 0x30)));
+        assert_eq!(delta.rows.len(), 5);
+
+        let mut copy = a.clone();
+        copy.take_events();
+        copy.take_dirty();
+        copy.apply_delta(&delta);
+        assert_same_state(&copy, &b);
+        assert_eq!((copy.delay(), copy.sound()), (0x30, 0x30));
+        assert!(copy.take_events().contains(&Chip8Event::ScreenChanged));
+        assert_eq!(&copy.take_dirty()[..6], &[true, true, true, true, true, false]);
+    }
+
+    #[test]
+    fn pressed_keys_are_held_until_released() {
+        let mut chip8 = Chip8::new();
+        chip8.press_key(3);
+        chip8.press_key(0xF);
+        let mut held = keys_with(3);
+        held[0xF] = true;
+        assert_eq!(chip8.keys(), held);
+
+        chip8.release_key(3);
+        assert_eq!(chip8.keys(), keys_with(0xF));
+
+        chip8.set_keys([false; 16]);
+        assert_eq!(chip8.keys(), [false; 16]);
+    }
+
+    #[test]
+    fn keys_past_0xf_are_ignored() {
+        let mut chip8 = Chip8::new();
+        chip8.press_key(0x10);
+        chip8.press_key(0xFF);
+        assert_eq!(chip8.keys(), [false; 16]);
+
+        chip8.set_keys(keys_with(2));
+        chip8.release_key(0x12);
+        assert_eq!(chip8.keys(), keys_with(2));
+    }
+
+    #[test]
+    fn stored_keys_finish_a_key_wait() {
+        // LD V1, K; LD V2, 1
+        let mut chip8 = machine(&[0xF10A, 0x6201]);
+        run(&mut chip8, 1);
+
+        chip8.press_key(9);
+        chip8.step_instruction(None).unwrap();
+        assert_eq!((chip8.pc, chip8.awaited_key_down), (0x200, Some(9)));
+
+        chip8.release_key(9);
+        chip8.step_instruction(None).unwrap();
+        assert_eq!((chip8.awaiting_key, chip8.regs[1], chip8.regs[2]), (None, 9, 1));
     }
 }
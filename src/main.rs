@@ -1,30 +1,374 @@
 
-extern crate rand;
+extern crate chip8;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+#[cfg(feature = "screenshot")]
+extern crate png;
+#[cfg(feature = "record")]
+extern crate gif;
 
 use std::env;
 use std::fs;
+use std::io::{self, Read};
+#[cfg(feature = "sdl")]
+use std::path::PathBuf;
+use std::process;
+
+// The emulator core is the library crate, see lib.rs
+use chip8::{machine, disasm};
 
-pub mod machine;
-pub mod opcode;
 pub mod frontend;
+pub mod config;
+pub mod replay;
+
+use machine::{Chip8, Chip8Builder, Condition, Platform, Watch, MEMORY_SIZE, PROGRAM_START};
+use frontend::{HeadlessFrontend, Frontend, Theme};
+#[cfg(feature = "sdl")]
+use frontend::{SdlFrontend, KeyLayout, Palette, keymap_from_names, parse_color};
+use config::Config;
+
+// Ten seconds of rewind at 60 frames a second
+#[cfg(feature = "sdl")]
+const REWIND_FRAMES: usize = 600;
+
+const DEFAULT_CONFIG_PATH: &'static str = "chip8.toml";
+
+fn usage() -> ! {
+    println!("Usage: chip8 [--disasm] [--info] [--headless N] [--speed N] [--platform NAME] [--seed N] [--start ADDR] [--theme NAME] [--scale-integer] [--profile] [--code-writes] [--trace FILE] [--watch WHAT] [--break ADDR] [--break-if COND] [--replay FILE] [--log-keys FILE] [--config FILE] <program>");
+    println!("  <program> can be - to read it from stdin");
+    println!("  --disasm         print the program's disassembly instead of running it");
+    println!("  --info           print the program's size, opcodes used and likely platform instead of running it");
+    println!("  --headless N     run N frames without a window, then print the screen");
+    println!("  --speed N        run N + 1 instructions per frame, N must be non-negative");
+    println!("  --platform NAME  use the quirks of vip, schip, xochip or modern (the default)");
+    println!("  --seed N         seed the random number generator so runs can be repeated");
+    println!("  --start ADDR     load the program at an address like 0x600 instead of 0x200, for the ETI-660");
+    println!("  --theme NAME     use the colors of classic, amber, green or lcd instead of the config's");
+    println!("  --scale-integer  scale the screen by whole pixels only, with borders to fill the window");
+    println!("  --profile        count the opcodes the program runs, printed on exit or with P");
+    println!("  --code-writes    list the writes the program makes into its own code, printed on exit");
+    println!("  --trace FILE     write every instruction executed and the registers to FILE");
+    println!("  --watch WHAT     print a register like V3 or an address like 0x300 as it changes while paused");
+    println!("                   O toggles a watch on the address in I, holding O and pressing 0 - F on a register");
+    println!("  --break ADDR     pause before running the instruction at an address like 0x2A0, H toggles one at pc");
+    println!("  --break-if COND  pause once a condition like \"VF == 1\" or \"I >= 0x300\" becomes true");
+    println!("  --replay FILE    feed the keys recorded in FILE to the program instead of the keyboard");
+    println!("  --log-keys FILE  save the keys held each frame to FILE on exit, for --replay");
+    println!("  --config FILE    read settings from FILE instead of {}", DEFAULT_CONFIG_PATH);
+    process::exit(1);
+}
 
-use machine::Chip8;
-use frontend::{SdlFrontend, Frontend};
+// A path of "-" reads the program from stdin
+fn read_program(path: &str) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    if path == "-" {
+        let stdin = io::stdin();
+        try!(stdin.lock().read_to_end(&mut bytes));
+    } else {
+        let mut file = try!(fs::File::open(path));
+        try!(file.read_to_end(&mut bytes));
+    }
+
+    Ok(bytes)
+}
+
+// Reads addresses like "0x2A0" or "672", anything past the end of memory is refused
+fn parse_addr(text: &str) -> Option<u16> {
+    let addr = if text.starts_with("0x") || text.starts_with("0X") {
+        u16::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
+    };
+
+    addr.filter(|addr| (*addr as usize) < MEMORY_SIZE)
+}
 
 fn main() {
-    let path = env::args().nth(1).unwrap();
+    let mut path = None;
+    let mut disassemble = false;
+    let mut show_info = false;
+    let mut headless_frames = None;
+    let mut speed = None;
+    let mut platform = Platform::Modern;
+    let mut seed = None;
+    let mut program_start = PROGRAM_START;
+    let mut theme = None;
+    let mut integer_scale = false;
+    let mut profiling = false;
+    let mut code_writes = false;
+    let mut trace_path = None;
+    let mut watches = Vec::new();
+    let mut breakpoints = Vec::new();
+    let mut conditions = Vec::new();
+    let mut replay_path = None;
+    let mut log_path = None;
+    let mut config_path = DEFAULT_CONFIG_PATH.to_string();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--disasm" => disassemble = true,
+            "--info" => show_info = true,
+            "--speed" => {
+                match args.next().and_then(|n| n.parse::<isize>().ok()) {
+                    Some(n) if n >= 0 => speed = Some(n),
+                    _ => usage(),
+                }
+            },
+            "--platform" => {
+                match args.next().and_then(|name| Platform::from_name(&name)) {
+                    Some(chosen) => platform = chosen,
+                    None => usage(),
+                }
+            },
+            "--seed" => {
+                match args.next().and_then(|n| n.parse::<u64>().ok()) {
+                    Some(n) => seed = Some(n),
+                    None => usage(),
+                }
+            },
+            "--start" => {
+                // The machine refuses starts that would overlap the fonts
+                match args.next().and_then(|text| parse_addr(&text)) {
+                    Some(addr) if Chip8::new().set_program_start(addr).is_ok() => program_start = addr,
+                    _ => usage(),
+                }
+            },
+            "--theme" => {
+                match args.next().and_then(|name| Theme::from_name(&name)) {
+                    Some(chosen) => theme = Some(chosen),
+                    None => usage(),
+                }
+            },
+            "--headless" => {
+                match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                    Some(n) => headless_frames = Some(n),
+                    None => usage(),
+                }
+            },
+            "--scale-integer" => integer_scale = true,
+            "--profile" => profiling = true,
+            "--code-writes" => code_writes = true,
+            "--trace" => trace_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--watch" => {
+                match args.next().and_then(|name| Watch::from_name(&name)) {
+                    Some(watch) => watches.push(watch),
+                    None => usage(),
+                }
+            },
+            "--break" => {
+                match args.next().and_then(|text| parse_addr(&text)) {
+                    Some(addr) => breakpoints.push(addr),
+                    None => usage(),
+                }
+            },
+            "--break-if" => {
+                match args.next().map(|text| text.parse::<Condition>()) {
+                    Some(Ok(condition)) => conditions.push(condition),
+                    Some(Err(err)) => {
+                        println!("{}", err);
+                        usage();
+                    },
+                    None => usage(),
+                }
+            },
+            "--replay" => replay_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--log-keys" => log_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--config" => config_path = args.next().unwrap_or_else(|| usage()),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| usage());
     
-    let file = fs::File::open(&path).unwrap_or_else(|err| {
-        panic!("Could not open program '{}': {}", path, err);
+    let bytes = read_program(&path).unwrap_or_else(|err| {
+        panic!("Could not read program '{}': {}", path, err);
     });
+
+    if bytes.is_empty() {
+        if path == "-" {
+            println!("No program was given on stdin");
+        } else {
+            println!("Program '{}' is empty", path);
+        }
+        process::exit(1);
+    }
+
+    // Checked before --disasm and --info too, so their addresses are ones the program could run at
+    if program_start as usize + bytes.len() > MEMORY_SIZE {
+        println!("Program '{}' is {} bytes, too large to start at 0x{:03X}", path, bytes.len(), program_start);
+        process::exit(1);
+    }
+
+    if disassemble {
+        for (addr, _, text) in disasm::disassemble(&bytes, program_start) {
+            println!("0x{:03X}: {}", addr, text);
+        }
+
+        return;
+    }
+
+    if show_info {
+        print!("{}", disasm::rom_info(&bytes));
+        return;
+    }
     
-    let mut chip8 = Chip8::new();
-    let mut sdl = SdlFrontend::new(sdl2::init().unwrap());
+    let config = Config::load(&config_path).unwrap_or_else(|err| {
+        println!("Could not read config '{}': {}", config_path, err);
+        process::exit(1);
+    });
+
+    let mut builder = Chip8Builder::new()
+        .platform(platform)
+        .speed(speed.unwrap_or(config.speed))
+        .program_start(program_start)
+        .rom(&bytes);
 
-    chip8.load_program(file).unwrap_or_else(|err| {
+    if let Some(seed) = seed {
+        builder = builder.seed(seed);
+    }
+
+    let mut chip8 = builder.build().unwrap_or_else(|err| {
         panic!("Could not load program '{}': {}", path, err);
     });
+    chip8.set_profiling(profiling);
+    chip8.set_watch_code_writes(code_writes);
+
+    for addr in breakpoints {
+        chip8.add_breakpoint(addr);
+    }
+    for condition in conditions {
+        chip8.add_condition(condition);
+    }
+
+    if let Some(trace_path) = trace_path {
+        let file = fs::File::create(&trace_path).unwrap_or_else(|err| {
+            println!("Could not create trace '{}': {}", trace_path, err);
+            process::exit(1);
+        });
+
+        chip8.set_trace(Some(Box::new(io::BufWriter::new(file))));
+    }
+
+    let replay = match replay_path {
+        Some(replay_path) => replay::load(&replay_path).unwrap_or_else(|err| {
+            println!("Could not read replay '{}': {}", replay_path, err);
+            process::exit(1);
+        }),
+        None => Vec::new(),
+    };
+
+    if let Some(frames) = headless_frames {
+        let mut headless = HeadlessFrontend::new(replay, frames);
+        headless.emulate_loop(&mut chip8);
+
+        let (width, height) = headless.last_size();
+        for row in headless.last_screen().iter().take(height) {
+            let line: String = row.iter().take(width).map(|pixel| if *pixel { '#' } else { ' ' }).collect();
+            println!("{}", line);
+        }
+
+        if chip8.profiling() {
+            print!("{}", chip8.profile_report());
+        }
+        if chip8.watching_code_writes() {
+            print!("{}", chip8.code_write_report());
+        }
+
+        return;
+    }
+
+    run_sdl(&mut chip8, &config, &config_path, &path, theme, integer_scale, watches, replay, log_path);
+
+    if chip8.profiling() {
+        print!("{}", chip8.profile_report());
+    }
+    if chip8.watching_code_writes() {
+        print!("{}", chip8.code_write_report());
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn run_sdl(chip8: &mut Chip8, config: &Config, config_path: &str, path: &str, theme: Option<Theme>, integer_scale: bool,
+           watches: Vec<Watch>, replay: Vec<[bool; 16]>, log_path: Option<String>) {
+    let invalid_config = |err: String| -> ! {
+        println!("Invalid setting in config '{}': {}", config_path, err);
+        process::exit(1);
+    };
+
+    let layout = match config.layout {
+        Some(ref name) => KeyLayout::from_name(name).unwrap_or_else(|| invalid_config(format!("unknown layout '{}'", name))),
+        None => KeyLayout::Custom(keymap_from_names(&config.keys).unwrap_or_else(|err| invalid_config(err))),
+    };
+    let fg = parse_color(&config.foreground).unwrap_or_else(|err| invalid_config(err));
+    let bg = parse_color(&config.background).unwrap_or_else(|err| invalid_config(err));
+
+    let mut sdl = match sdl2::init().and_then(|ctx| SdlFrontend::new(ctx, REWIND_FRAMES, layout, config.scale, config.vsync)) {
+        Ok(sdl) => sdl,
+        Err(err) => {
+            println!("Could not start SDL: {}", err);
+            process::exit(1);
+        },
+    };
+
+    // A theme takes the place of the configured colors
+    match theme {
+        Some(theme) => {
+            sdl.palette = theme.palette();
+            sdl.ghosting = theme.ghosting();
+        },
+        None => sdl.palette = Palette::new(bg, fg),
+    }
+    sdl.target_fps = config.fps;
+    sdl.integer_scale = integer_scale;
+    sdl.watches = watches;
+    sdl.replay = replay.into_iter().collect();
+
+    if log_path.is_some() {
+        sdl.input_log = Some(Vec::new());
+    }
+
+    // Keep save states next to the ROM they belong to
+    if path != "-" {
+        sdl.state_path = PathBuf::from(format!("{}.state", path));
+    }
     
     sdl.emulate_loop(chip8);
+
+    if let (Some(log_path), Some(log)) = (log_path, sdl.input_log.take()) {
+        if let Err(err) = replay::save(&log_path, &log) {
+            println!("Could not save input to '{}': {}", log_path, err);
+        }
+    }
+}
+
+// Without SDL there's nothing to open a window with, only --headless and --disasm work
+#[cfg(not(feature = "sdl"))]
+fn run_sdl(_chip8: &mut Chip8, _config: &Config, _config_path: &str, _path: &str, _theme: Option<Theme>, _integer_scale: bool,
+           _watches: Vec<Watch>, _replay: Vec<[bool; 16]>, _log_path: Option<String>) {
+    println!("This build has no SDL support, run with --headless N instead");
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_parse() {
+        assert_eq!(parse_addr("0x2A0"), Some(0x2A0));
+        assert_eq!(parse_addr("0X2a0"), Some(0x2A0));
+        assert_eq!(parse_addr("672"), Some(0x2A0));
+        assert_eq!(parse_addr("0xFFF"), Some(0xFFF));
+
+        for text in &["0x1000", "4096", "V3", "0x", "", "-1"] {
+            assert_eq!(parse_addr(text), None, "{}", text);
+        }
+    }
 }
@@ -1,13 +1,25 @@
+use std::fmt;
 
 pub type OpcodeResult = Result<Opcode, OpcodeError>;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum OpcodeError {
     UnrecognizedOpcode(u16),
     InvalidModeForSetRegToReg(u8),
+    PastEndOfMemory(u16), // Address of an opcode that doesn't fit before the end of memory
 }
 
-#[derive(Debug, PartialEq)]
+impl fmt::Display for OpcodeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpcodeError::UnrecognizedOpcode(bytes) => write!(fmt, "unrecognized opcode 0x{:04X}", bytes),
+            OpcodeError::InvalidModeForSetRegToReg(mode) => write!(fmt, "invalid mode 0x{:X} for 8XYN", mode),
+            OpcodeError::PastEndOfMemory(addr) => write!(fmt, "no room for an opcode at 0x{:03X} before the end of memory", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SetRegMode {
     Copy = 0x0, // VX = VY
 
@@ -41,9 +53,14 @@ impl SetRegMode {
             _ => None,
         }
     }
+
+    // The low nibble of the 8XYN opcode, the inverse of `from_u8`
+    pub fn to_u8(&self) -> u8 {
+        *self as u8
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Opcode {
     /* KEY
      * NNNN => address,
@@ -53,6 +70,12 @@ pub enum Opcode {
      */
     ClearScreen,    // 0x00E0
     Return,         // 0x00EE
+    LowRes,         // 0x00FE, SUPER-CHIP
+    HighRes,        // 0x00FF, SUPER-CHIP
+    ScrollDown(u8), // 0x00CN, SUPER-CHIP, scrolls the screen down N lines
+    ScrollRight,    // 0x00FB, SUPER-CHIP, scrolls the screen right 4 pixels
+    ScrollLeft,     // 0x00FC, SUPER-CHIP, scrolls the screen left 4 pixels
+    Exit,           // 0x00FD, SUPER-CHIP, stops the interpreter
     JumpTo {        // 1NNN | BNNN
         addr: u16,
         plus_v0: bool
@@ -87,11 +110,13 @@ pub enum Opcode {
         mask: u8,
     },
 
-    DrawSprite { // DXYN, draws from sprite addr stored in I. Each sprite is 8 bits wide
+    DrawSprite { // DXYN, draws from sprite addr stored in I. Each sprite is 8 bits wide, except DXY0 in high resolution which is 16x16
         regs: (u8, u8),
         rows: u8,
     },
 
+    SelectPlanes(u8), // FN01, XO-CHIP, N is a bitmask of the planes to draw to
+
     SkipIfKeyInRegPressed { // EX9E | EXA1 
         not_pressed: bool,
         reg: u8,
@@ -105,10 +130,14 @@ pub enum Opcode {
 
     AddRegToAddressReg(u8), // FX1E
     SetAddressRegToCharInReg(u8), // FX29, sets the address pointer to point to the text character specified in X
+    SetAddressRegToLargeCharInReg(u8), // FX30, SUPER-CHIP, same as FX29 but for the 8x10 font
     RegToBCD(u8), // FX33, see http://en.wikipedia.org/wiki/Binary-coded_decimal
     
     DumpRegsToAddr(u8), // FX55
     LoadRegsFromAddr(u8), // FX65
+
+    DumpRegsToFlags(u8), // FX75, SUPER-CHIP, stores V0 - VX in the RPL user flags, X is at most 7
+    LoadRegsFromFlags(u8), // FX85, SUPER-CHIP
 }
 
 impl Opcode {
@@ -118,18 +147,16 @@ impl Opcode {
 
         let msb = bytes & 0xF000;
         match msb {
-            0x0000 => {
-                match bytes & 0x0F00 {
-                    0x000 => match bytes & 0x00F0 {
-                        0xE0 => match bytes & 0x000F {
-                            0x0 => Ok(Opcode::ClearScreen),
-                            0xE => Ok(Opcode::Return),
-                            _ => Err(UnrecognizedOpcode(bytes)),
-                        },
-                        _ => Err(UnrecognizedOpcode(bytes)),
-                    },
-                    _ => Err(UnrecognizedOpcode(bytes)),
-                }
+            0x0000 => match bytes & 0x0FFF {
+                0x0E0 => Ok(ClearScreen),
+                0x0EE => Ok(Return),
+                0x0FE => Ok(LowRes),
+                0x0FF => Ok(HighRes),
+                0x0FB => Ok(ScrollRight),
+                0x0FC => Ok(ScrollLeft),
+                0x0FD => Ok(Exit),
+                low if low & 0xFF0 == 0x0C0 => Ok(ScrollDown((low & 0x00F) as u8)),
+                _ => Err(UnrecognizedOpcode(bytes)),
             },
             0x1000 | 0xB000 => {
                 Ok(JumpTo { 
@@ -204,19 +231,224 @@ impl Opcode {
                 let reg = ((bytes & 0x0F00) >> 8) as u8;
 
                 match bytes & 0x00FF {
+                    0x01 => Ok(SelectPlanes(reg)),
                     0x07 => Ok(SetRegToDelayTimer(reg)),
                     0x0A => Ok(WaitForKeyInReg(reg)),
                     0x15 => Ok(SetDelayTimerToReg(reg)),
                     0x18 => Ok(SetSoundTimerToReg(reg)),
                     0x1E => Ok(AddRegToAddressReg(reg)),
                     0x29 => Ok(SetAddressRegToCharInReg(reg)),
+                    0x30 => Ok(SetAddressRegToLargeCharInReg(reg)),
                     0x33 => Ok(RegToBCD(reg)),
                     0x55 => Ok(DumpRegsToAddr(reg)),
                     0x65 => Ok(LoadRegsFromAddr(reg)),
+                    0x75 => Ok(DumpRegsToFlags(reg)),
+                    0x85 => Ok(LoadRegsFromFlags(reg)),
                     _ => Err(UnrecognizedOpcode(bytes)),
                 }
             },
             _ => Err(UnrecognizedOpcode(bytes)),
         }
     }
+
+    // The variant's name without its operands, for grouping opcodes together
+    pub fn name(&self) -> &'static str {
+        use self::Opcode::*;
+
+        match *self {
+            ClearScreen => "ClearScreen",
+            Return => "Return",
+            LowRes => "LowRes",
+            HighRes => "HighRes",
+            ScrollDown(..) => "ScrollDown",
+            ScrollRight => "ScrollRight",
+            ScrollLeft => "ScrollLeft",
+            Exit => "Exit",
+            JumpTo { .. } => "JumpTo",
+            Call(..) => "Call",
+            SkipIfRegEqualConst { .. } => "SkipIfRegEqualConst",
+            SkipIfRegsEqual { .. } => "SkipIfRegsEqual",
+            SetRegToConst { .. } => "SetRegToConst",
+            SetRegToReg { .. } => "SetRegToReg",
+            SetAddressReg(..) => "SetAddressReg",
+            SetRegToRandom { .. } => "SetRegToRandom",
+            DrawSprite { .. } => "DrawSprite",
+            SelectPlanes(..) => "SelectPlanes",
+            SkipIfKeyInRegPressed { .. } => "SkipIfKeyInRegPressed",
+            WaitForKeyInReg(..) => "WaitForKeyInReg",
+            SetRegToDelayTimer(..) => "SetRegToDelayTimer",
+            SetDelayTimerToReg(..) => "SetDelayTimerToReg",
+            SetSoundTimerToReg(..) => "SetSoundTimerToReg",
+            AddRegToAddressReg(..) => "AddRegToAddressReg",
+            SetAddressRegToCharInReg(..) => "SetAddressRegToCharInReg",
+            SetAddressRegToLargeCharInReg(..) => "SetAddressRegToLargeCharInReg",
+            RegToBCD(..) => "RegToBCD",
+            DumpRegsToAddr(..) => "DumpRegsToAddr",
+            LoadRegsFromAddr(..) => "LoadRegsFromAddr",
+            DumpRegsToFlags(..) => "DumpRegsToFlags",
+            LoadRegsFromFlags(..) => "LoadRegsFromFlags",
+        }
+    }
+
+    // Encodes back into the 16 bit form that `from_u16` decodes, so that
+    // `Opcode::from_u16(x).map(|op| op.to_u16()) == Ok(x)` for every opcode it recognizes.
+    // Fields wider than their nibble (a register above 0xF, say) are not masked off
+    pub fn to_u16(&self) -> u16 {
+        use self::Opcode::*;
+
+        let reg = |reg: u8| (reg as u16) << 8;
+        let regs = |(v_x, v_y): (u8, u8)| (v_x as u16) << 8 | (v_y as u16) << 4;
+
+        match *self {
+            ClearScreen => 0x00E0,
+            Return => 0x00EE,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            ScrollDown(lines) => 0x00C0 | lines as u16,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+
+            JumpTo { addr, plus_v0 } => if plus_v0 { 0xB000 | addr } else { 0x1000 | addr },
+            Call(addr) => 0x2000 | addr,
+
+            SkipIfRegEqualConst { not_equal, reg: v_x, value } => {
+                (if not_equal { 0x4000 } else { 0x3000 }) | reg(v_x) | value as u16
+            },
+            SkipIfRegsEqual { not_equal, regs: v_xy } => {
+                (if not_equal { 0x9000 } else { 0x5000 }) | regs(v_xy)
+            },
+
+            SetRegToConst { add, reg: v_x, value } => {
+                (if add { 0x7000 } else { 0x6000 }) | reg(v_x) | value as u16
+            },
+            SetRegToReg { regs: v_xy, mode } => 0x8000 | regs(v_xy) | mode.to_u8() as u16,
+
+            SetAddressReg(addr) => 0xA000 | addr,
+            SetRegToRandom { reg: v_x, mask } => 0xC000 | reg(v_x) | mask as u16,
+
+            DrawSprite { regs: v_xy, rows } => 0xD000 | regs(v_xy) | rows as u16,
+
+            SelectPlanes(mask) => 0xF001 | reg(mask),
+
+            SkipIfKeyInRegPressed { not_pressed, reg: v_x } => {
+                0xE000 | reg(v_x) | if not_pressed { 0xA1 } else { 0x9E }
+            },
+            WaitForKeyInReg(v_x) => 0xF00A | reg(v_x),
+
+            SetRegToDelayTimer(v_x) => 0xF007 | reg(v_x),
+
+            SetDelayTimerToReg(v_x) => 0xF015 | reg(v_x),
+            SetSoundTimerToReg(v_x) => 0xF018 | reg(v_x),
+
+            AddRegToAddressReg(v_x) => 0xF01E | reg(v_x),
+            SetAddressRegToCharInReg(v_x) => 0xF029 | reg(v_x),
+            SetAddressRegToLargeCharInReg(v_x) => 0xF030 | reg(v_x),
+            RegToBCD(v_x) => 0xF033 | reg(v_x),
+
+            DumpRegsToAddr(v_x) => 0xF055 | reg(v_x),
+            LoadRegsFromAddr(v_x) => 0xF065 | reg(v_x),
+            DumpRegsToFlags(v_x) => 0xF075 | reg(v_x),
+            LoadRegsFromFlags(v_x) => 0xF085 | reg(v_x),
+        }
+    }
+}
+
+// Uses the common CHIP-8 assembly syntax, see http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+impl fmt::Display for Opcode {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::Opcode::*;
+
+        match *self {
+            ClearScreen => write!(fmt, "CLS"),
+            Return => write!(fmt, "RET"),
+            LowRes => write!(fmt, "LOW"),
+            HighRes => write!(fmt, "HIGH"),
+            ScrollDown(lines) => write!(fmt, "SCD {}", lines),
+            ScrollRight => write!(fmt, "SCR"),
+            ScrollLeft => write!(fmt, "SCL"),
+            Exit => write!(fmt, "EXIT"),
+
+            JumpTo { addr, plus_v0: false } => write!(fmt, "JP 0x{:03X}", addr),
+            JumpTo { addr, plus_v0: true } => write!(fmt, "JP V0, 0x{:03X}", addr),
+            Call(addr) => write!(fmt, "CALL 0x{:03X}", addr),
+
+            SkipIfRegEqualConst { not_equal, reg, value } => {
+                write!(fmt, "{} V{:X}, 0x{:02X}", if not_equal { "SNE" } else { "SE" }, reg, value)
+            },
+            SkipIfRegsEqual { not_equal, regs: (v_x, v_y) } => {
+                write!(fmt, "{} V{:X}, V{:X}", if not_equal { "SNE" } else { "SE" }, v_x, v_y)
+            },
+
+            SetRegToConst { add, reg, value } => {
+                write!(fmt, "{} V{:X}, 0x{:02X}", if add { "ADD" } else { "LD" }, reg, value)
+            },
+            SetRegToReg { regs: (v_x, v_y), mode } => {
+                let name = match mode {
+                    SetRegMode::Copy => "LD",
+                    SetRegMode::Or => "OR",
+                    SetRegMode::And => "AND",
+                    SetRegMode::Xor => "XOR",
+                    SetRegMode::Add => "ADD",
+                    SetRegMode::Subtract => "SUB",
+                    SetRegMode::InverseSubtract => "SUBN",
+                    SetRegMode::ShiftLeft => "SHL",
+                    SetRegMode::ShiftRight => "SHR",
+                };
+
+                write!(fmt, "{} V{:X}, V{:X}", name, v_x, v_y)
+            },
+
+            SetAddressReg(addr) => write!(fmt, "LD I, 0x{:03X}", addr),
+            SetRegToRandom { reg, mask } => write!(fmt, "RND V{:X}, 0x{:02X}", reg, mask),
+
+            DrawSprite { regs: (v_x, v_y), rows } => write!(fmt, "DRW V{:X}, V{:X}, {}", v_x, v_y, rows),
+
+            SelectPlanes(mask) => write!(fmt, "PLANE {}", mask),
+
+            SkipIfKeyInRegPressed { not_pressed, reg } => {
+                write!(fmt, "{} V{:X}", if not_pressed { "SKNP" } else { "SKP" }, reg)
+            },
+            WaitForKeyInReg(reg) => write!(fmt, "LD V{:X}, K", reg),
+
+            SetRegToDelayTimer(reg) => write!(fmt, "LD V{:X}, DT", reg),
+
+            SetDelayTimerToReg(reg) => write!(fmt, "LD DT, V{:X}", reg),
+            SetSoundTimerToReg(reg) => write!(fmt, "LD ST, V{:X}", reg),
+
+            AddRegToAddressReg(reg) => write!(fmt, "ADD I, V{:X}", reg),
+            SetAddressRegToCharInReg(reg) => write!(fmt, "LD F, V{:X}", reg),
+            SetAddressRegToLargeCharInReg(reg) => write!(fmt, "LD HF, V{:X}", reg),
+            RegToBCD(reg) => write!(fmt, "LD B, V{:X}", reg),
+
+            DumpRegsToAddr(reg) => write!(fmt, "LD [I], V{:X}", reg),
+            LoadRegsFromAddr(reg) => write!(fmt, "LD V{:X}, [I]", reg),
+            DumpRegsToFlags(reg) => write!(fmt, "LD R, V{:X}", reg),
+            LoadRegsFromFlags(reg) => write!(fmt, "LD V{:X}, R", reg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_word_round_trips() {
+        for word in 0..0x10000u32 {
+            let word = word as u16;
+
+            if let Ok(opcode) = Opcode::from_u16(word) {
+                assert_eq!(opcode.to_u16(), word, "{} decoded from 0x{:04X}", opcode, word);
+            }
+        }
+    }
+
+    #[test]
+    fn undecodable_words_are_rejected() {
+        assert!(Opcode::from_u16(0x5121).is_err()); // 5XY0 with a nonzero last nibble
+        assert!(Opcode::from_u16(0x8128).is_err()); // No 8XY8
+        assert!(Opcode::from_u16(0xE1FF).is_err());
+        assert!(Opcode::from_u16(0xF1FF).is_err());
+    }
 }
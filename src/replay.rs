@@ -0,0 +1,82 @@
+use std::io::{self, BufRead, Write};
+use std::fs;
+use std::path::Path;
+
+/* Recorded input is a text file with one line per frame, holding the keys that were
+ * down as a hex bitmask where bit N is key N. So this is a frame with nothing held,
+ * then one with key 5 held, then one with keys 0 and 5 held:
+ *
+ *   0000
+ *   0020
+ *   0021
+ *
+ * Played back with the same --seed, a recording reproduces a run exactly
+ */
+
+pub fn save<P: AsRef<Path>>(path: P, frames: &[[bool; 16]]) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(try!(fs::File::create(path)));
+
+    for keys in frames.iter() {
+        let mask = keys.iter().enumerate().fold(0u16, |mask, (key, held)| mask | (*held as u16) << key);
+        try!(writeln!(writer, "{:04X}", mask));
+    }
+
+    writer.flush()
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<[bool; 16]>> {
+    let reader = io::BufReader::new(try!(fs::File::open(path)));
+    let mut frames = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = try!(line);
+
+        let mask = try!(u16::from_str_radix(line.trim(), 16).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: '{}' is not a key mask", index + 1, line))
+        }));
+
+        let mut keys = [false; 16];
+        for (key, held) in keys.iter_mut().enumerate() {
+            *held = mask & (1 << key) != 0;
+        }
+        frames.push(keys);
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn save_then_load() {
+        let mut frames = vec![[false; 16]; 3];
+        frames[1][5] = true;
+        frames[2][0] = true;
+        frames[2][5] = true;
+        frames[2][0xF] = true;
+
+        let path = env::temp_dir().join(format!("chip8-replay-test-{}.txt", process::id()));
+        save(&path, &frames).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(text, "0000\n0020\n8021\n");
+        assert_eq!(loaded.unwrap(), frames);
+    }
+
+    #[test]
+    fn bad_lines_are_reported() {
+        let path = env::temp_dir().join(format!("chip8-replay-bad-{}.txt", process::id()));
+        fs::write(&path, "0000\nzz\n").unwrap();
+        let err = load(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"));
+    }
+}
@@ -0,0 +1,66 @@
+// Runs the ROMs in tests/fixtures and compares the screen they leave behind to the snapshot
+// next to them.  The .asm files are the source for each .ch8, and setting UPDATE_SNAPSHOTS
+// rewrites the snapshots from the current behavior instead of checking them
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use headless::HeadlessFrontend;
+use machine::Chip8Builder;
+use testutil::{assert_screens_eq, lores, screen_from_text, screen_to_text, LoresScreen};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)
+}
+
+fn read_fixture(name: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    File::open(fixture(name)).and_then(|mut file| file.read_to_end(&mut bytes)).expect(name);
+
+    bytes
+}
+
+// Runs `rom` from a fixed seed for `frames` frames, the same as --headless with no keys held
+fn run_rom(rom: &[u8], frames: usize) -> LoresScreen {
+    let mut chip8 = Chip8Builder::new().seed(0).rom(rom).build().expect("ROM doesn't load");
+
+    let mut headless = HeadlessFrontend::new(Vec::new(), frames);
+    headless.run(&mut chip8).unwrap_or_else(|err| panic!("{}", err));
+
+    lores(headless.last_screen())
+}
+
+fn check_snapshot(rom: &str, frames: usize) {
+    let screen = run_rom(&read_fixture(&format!("{}.ch8", rom)), frames);
+    let snapshot = format!("{}.txt", rom);
+
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        let mut file = File::create(fixture(&snapshot)).unwrap();
+        file.write_all(screen_to_text(&screen).as_bytes()).unwrap();
+        return;
+    }
+
+    let text = String::from_utf8(read_fixture(&snapshot)).unwrap();
+    assert_screens_eq(&screen, &screen_from_text(&text).expect(&snapshot));
+}
+
+#[test]
+fn opcodes() {
+    check_snapshot("opcodes", 60);
+}
+
+#[test]
+fn digits() {
+    check_snapshot("digits", 10);
+}
+
+#[test]
+fn delay_wait() {
+    // Nothing is drawn until the delay timer runs out after 30 frames
+    let rom = read_fixture("delay_wait.ch8");
+    assert_screens_eq(&run_rom(&rom, 29), &[[false; 64]; 32]);
+
+    check_snapshot("delay_wait", 60);
+}
@@ -0,0 +1,140 @@
+// Helpers for tests that check what a program left on the screen.  Snapshots are the
+// low resolution 64x32 screen written out as text, one line per row with 1 for a lit pixel
+
+use machine::Screen;
+
+pub type LoresScreen = [[bool; 64]; 32];
+
+// The top left 64x32 corner of `screen`, which is all of it in low resolution
+pub fn lores(screen: &Screen) -> LoresScreen {
+    let mut lores = [[false; 64]; 32];
+    for (y, row) in lores.iter_mut().enumerate() {
+        row.copy_from_slice(&screen[y][..64]);
+    }
+
+    lores
+}
+
+// A screen that only has `rows` drawn in its top left corner, so tests that draw a sprite or two
+// don't need a whole screen written out.  Rows are in `screen_to_text`'s format
+pub fn screen_from_rows(rows: &[&str]) -> LoresScreen {
+    let mut screen = [[false; 64]; 32];
+    for (y, row) in rows.iter().enumerate() {
+        for (x, ch) in row.chars().enumerate() {
+            screen[y][x] = ch == '1';
+        }
+    }
+
+    screen
+}
+
+pub fn screen_to_text(screen: &LoresScreen) -> String {
+    let mut text = String::with_capacity(65 * 32);
+    for row in screen.iter() {
+        text.extend(row.iter().map(|pixel| if *pixel { '1' } else { '0' }));
+        text.push('\n');
+    }
+
+    text
+}
+
+// Reads back what `screen_to_text` wrote.  Whitespace around each row and blank lines are
+// skipped, so snapshots can be indented inside string literals
+pub fn screen_from_text(text: &str) -> Result<LoresScreen, String> {
+    let rows: Vec<&str> = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).collect();
+    if rows.len() != 32 {
+        return Err(format!("expected 32 rows, got {}", rows.len()));
+    }
+
+    let mut screen = [[false; 64]; 32];
+    for (y, row) in rows.iter().enumerate() {
+        if row.len() != 64 {
+            return Err(format!("row {} is {} pixels wide instead of 64", y, row.len()));
+        }
+
+        for (x, ch) in row.chars().enumerate() {
+            screen[y][x] = match ch {
+                '1' => true,
+                '0' => false,
+                _ => return Err(format!("row {} has '{}' where a 0 or 1 should be", y, ch)),
+            };
+        }
+    }
+
+    Ok(screen)
+}
+
+// Panics with both screens laid over each other when they differ.  A # is lit in both,
+// + is only lit in `actual` and - is only lit in `expected`
+pub fn assert_screens_eq(actual: &LoresScreen, expected: &LoresScreen) {
+    if actual[..] == expected[..] {
+        return;
+    }
+
+    let mut diff = String::new();
+    let mut changed = 0;
+
+    for y in 0..32 {
+        for x in 0..64 {
+            diff.push(match (actual[y][x], expected[y][x]) {
+                (true, true) => '#',
+                (true, false) => '+',
+                (false, true) => '-',
+                (false, false) => '.',
+            });
+            changed += (actual[y][x] != expected[y][x]) as usize;
+        }
+        diff.push('\n');
+    }
+
+    panic!("screens differ in {} pixels (+ only in actual, - only in expected):\n{}", changed, diff);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trips() {
+        let mut screen = [[false; 64]; 32];
+        screen[0][0] = true;
+        screen[31][63] = true;
+        screen[10][20] = true;
+
+        let text = screen_to_text(&screen);
+        assert_eq!(text.lines().count(), 32);
+        assert!(text.starts_with("1000"));
+
+        assert_screens_eq(&screen_from_text(&text).unwrap(), &screen);
+    }
+
+    #[test]
+    fn lores_is_the_top_left_corner() {
+        let mut screen = [[false; 128]; 64];
+        screen[0][63] = true;
+        screen[0][64] = true;
+        screen[32][0] = true;
+
+        let corner = lores(&screen);
+        assert!(corner[0][63]);
+        assert_eq!(corner.iter().flat_map(|row| row.iter()).filter(|pixel| **pixel).count(), 1);
+    }
+
+    #[test]
+    fn bad_text_is_rejected() {
+        assert!(screen_from_text("0101").is_err());
+
+        let mut text = screen_to_text(&[[false; 64]; 32]);
+        text.replace_range(3..4, "x");
+        assert!(screen_from_text(&text).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "screens differ in 1 pixels")]
+    fn differing_screens_panic() {
+        let mut screen = [[false; 64]; 32];
+        screen[5][5] = true;
+
+        assert_screens_eq(&screen, &[[false; 64]; 32]);
+    }
+}
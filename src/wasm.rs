@@ -0,0 +1,92 @@
+use machine::{Chip8, RuntimeError, SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// Browsers own the event loop, so rather than looping itself this is
+// stepped a frame at a time from JavaScript's requestAnimationFrame
+pub struct WasmFrontend {
+    pub chip8: Chip8,
+
+    // One byte per pixel, row by row across the full 128x64 buffer.
+    // Bit 0 is set for the first plane and bit 1 for XO-CHIP's second plane
+    pixels: Vec<u8>,
+}
+
+impl WasmFrontend {
+    pub fn new(chip8: Chip8) -> WasmFrontend {
+        let mut frontend = WasmFrontend {
+            chip8: chip8,
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        };
+
+        frontend.update_pixels();
+        frontend
+    }
+
+    // Runs one frame worth of instructions and ticks the timers once, so it's meant
+    // to be called 60 times a second
+    pub fn wasm_step(&mut self, keys: &[bool; 16]) -> Result<(), RuntimeError> {
+        try!(self.chip8.cycle(Some(*keys)));
+        self.chip8.tick_timers();
+
+        self.update_pixels();
+        Ok(())
+    }
+
+    // Same as `wasm_step`, but for displays that aren't 60Hz the timers follow `micros`,
+    // the time since the last call, instead of ticking once
+    pub fn wasm_step_timed(&mut self, keys: &[bool; 16], micros: u64) -> Result<(), RuntimeError> {
+        try!(self.chip8.cycle(Some(*keys)));
+        self.chip8.advance_timers(micros);
+
+        self.update_pixels();
+        Ok(())
+    }
+
+    pub fn screen_buffer(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    // Width and height of the part of `screen_buffer` in use, rows are always `SCREEN_WIDTH` long
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.chip8.screen_size()
+    }
+
+    pub fn sound_playing(&self) -> bool {
+        self.chip8.sound_playing()
+    }
+
+    fn update_pixels(&mut self) {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let first = self.chip8.screen()[y][x] as u8;
+                let second = self.chip8.second_plane()[y][x] as u8;
+
+                self.pixels[y * SCREEN_WIDTH + x] = first | second << 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Chip8Builder;
+
+    #[test]
+    fn step_draws_into_the_buffer() {
+        // LD V0, 0xA; LD F, V0; DRW V0, V0, 5; LD V1, 2; LD DT, V1; JP 0x20A
+        let rom = [0x60, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0x61, 0x02, 0xF1, 0x15, 0x12, 0x0A];
+        let mut frontend = WasmFrontend::new(Chip8Builder::new().rom(&rom).build().unwrap());
+        assert!(frontend.screen_buffer().iter().all(|pixel| *pixel == 0));
+
+        frontend.wasm_step(&[false; 16]).unwrap();
+        assert_eq!(frontend.screen_size(), (64, 32));
+
+        // The top row of the A glyph, 0xF0, at (10, 10)
+        let row = &frontend.screen_buffer()[10 * SCREEN_WIDTH..11 * SCREEN_WIDTH];
+        assert_eq!(&row[9..15], &[0, 1, 1, 1, 1, 0]);
+        assert_eq!(frontend.chip8.delay(), 1);
+
+        frontend.wasm_step_timed(&[false; 16], 20_000).unwrap();
+        assert_eq!(frontend.chip8.delay(), 0);
+    }
+}
@@ -0,0 +1,20 @@
+; Waits out half a second on the delay timer before drawing a column of 8s,
+; so the screen is blank for the first 30 frames
+
+    LD V3, 30
+    LD DT, V3
+wait:
+    LD V3, DT
+    SE V3, 0
+    JP wait
+    LD V0, 8
+    LD F, V0
+    LD V1, 30
+    LD V2, 2
+draw:
+    DRW V1, V2, 5
+    ADD V2, 6
+    SE V2, 32
+    JP draw
+end:
+    JP end
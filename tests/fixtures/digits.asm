@@ -0,0 +1,19 @@
+; Draws the 16 hex digits from the built in font in a 4x4 grid, the last column
+; hanging off the right edge to check that sprites wrap around
+
+    LD V0, 0
+    LD V2, 0
+next_row:
+    LD V1, 14
+next_digit:
+    LD F, V0
+    DRW V1, V2, 5
+    ADD V0, 1
+    ADD V1, 16
+    SE V1, 78
+    JP next_digit
+    ADD V2, 8
+    SE V2, 32
+    JP next_row
+end:
+    JP end
@@ -0,0 +1,212 @@
+; Checks one opcode at a time and draws a digit for each: 1 if it behaved, 0 if it didn't,
+; so a passing run is two rows of 1s.  Every check leaves its answer in V3 (V0 for the skips),
+; VA and VB are the cursor for `result`
+
+    LD VA, 0
+    LD VB, 1
+
+; 6XNN
+    LD V3, 0x42
+    LD V0, 0
+    SNE V3, 0x42
+    LD V0, 1
+    CALL result
+
+; 7XNN wraps without touching VF
+    LD VF, 0
+    LD V3, 0xFF
+    ADD V3, 2
+    ADD V3, VF
+    LD V0, 0
+    SNE V3, 0x01
+    LD V0, 1
+    CALL result
+
+; 8XY0
+    LD V4, 0x17
+    LD V3, V4
+    LD V0, 0
+    SNE V3, 0x17
+    LD V0, 1
+    CALL result
+
+; 8XY1
+    LD V3, 0x0F
+    LD V4, 0xF0
+    OR V3, V4
+    LD V0, 0
+    SNE V3, 0xFF
+    LD V0, 1
+    CALL result
+
+; 8XY2
+    LD V3, 0x3C
+    LD V4, 0x0F
+    AND V3, V4
+    LD V0, 0
+    SNE V3, 0x0C
+    LD V0, 1
+    CALL result
+
+; 8XY3
+    LD V3, 0xFF
+    LD V4, 0x0F
+    XOR V3, V4
+    LD V0, 0
+    SNE V3, 0xF0
+    LD V0, 1
+    CALL result
+
+; 8XY4 with a carry, then the carry itself
+    LD V3, 0xF0
+    LD V4, 0x20
+    ADD V3, V4
+    LD V5, VF
+    LD V0, 0
+    SNE V3, 0x10
+    LD V0, 1
+    CALL result
+    LD V3, V5
+    LD V0, 0
+    SNE V3, 1
+    LD V0, 1
+    CALL result
+
+; 8XY5 without a borrow sets VF
+    LD V3, 0x0A
+    LD V4, 0x05
+    SUB V3, V4
+    LD V5, VF
+    LD V0, 0
+    SNE V3, 0x05
+    LD V0, 1
+    CALL result
+    LD V3, V5
+    LD V0, 0
+    SNE V3, 1
+    LD V0, 1
+    CALL result
+
+; 8XY7 with a borrow clears it
+    LD V3, 0x0A
+    LD V4, 0x05
+    SUBN V3, V4
+    LD V5, VF
+    LD V0, 0
+    SNE V3, 0xFB
+    LD V0, 1
+    CALL result
+    LD V3, V5
+    LD V0, 0
+    SNE V3, 0
+    LD V0, 1
+    CALL result
+
+; 8XY6 and 8XYE, with VX == VY so the shift quirk doesn't matter
+    LD V3, 0x81
+    LD V4, 0x81
+    SHR V3, V4
+    LD V0, 0
+    SNE V3, 0x40
+    LD V0, 1
+    CALL result
+    LD V3, 0x81
+    LD V4, 0x81
+    SHL V3, V4
+    LD V0, 0
+    SNE V3, 0x02
+    LD V0, 1
+    CALL result
+
+; 5XY0 skips on equal registers, 9XY0 on different ones
+    LD V3, 7
+    LD V4, 7
+    LD V0, 1
+    SE V3, V4
+    LD V0, 0
+    CALL result
+    LD V4, 8
+    LD V0, 1
+    SNE V3, V4
+    LD V0, 0
+    CALL result
+
+; 2NNN and 00EE
+    LD V3, 0
+    CALL set_v3
+    LD V0, 0
+    SNE V3, 0x55
+    LD V0, 1
+    CALL result
+
+; FX33
+    LD V3, 234
+    LD I, scratch
+    LD B, V3
+    LD V2, [I]
+    LD V3, V1
+    LD V0, 0
+    SNE V3, 3
+    LD V0, 1
+    CALL result
+
+; FX55 and FX65
+    LD V0, 1
+    LD V1, 2
+    LD V2, 3
+    LD V3, 4
+    LD I, scratch
+    LD [I], V3
+    LD V3, 0
+    LD I, scratch
+    LD V3, [I]
+    LD V0, 0
+    SNE V3, 4
+    LD V0, 1
+    CALL result
+
+; EXA1 with nothing held
+    LD V3, 5
+    LD V0, 1
+    SKNP V3
+    LD V0, 0
+    CALL result
+
+; CXNN with an empty mask
+    RND V3, 0
+    LD V0, 0
+    SNE V3, 0
+    LD V0, 1
+    CALL result
+
+; DXYN sets VF when drawing over itself, and leaves the screen as it was
+    LD V3, 56
+    LD V4, 26
+    LD I, block
+    DRW V3, V4, 4
+    DRW V3, V4, 4
+    LD V3, VF
+    LD V0, 0
+    SNE V3, 1
+    LD V0, 1
+    CALL result
+
+end:
+    JP end
+
+result:
+    LD F, V0
+    DRW VA, VB, 5
+    ADD VA, 5
+    SE VA, 60
+    RET
+    LD VA, 0
+    ADD VB, 6
+    RET
+
+set_v3:
+    LD V3, 0x55
+    RET
+
+block: DB 0xF0, 0xF0, 0xF0, 0xF0
+scratch: DB 0, 0, 0, 0